@@ -0,0 +1,414 @@
+//! Static asset pipeline
+//!
+//! build_static_assets() copies every file under the static directory (config static_dir, or /static/ under
+//! local_content_dir) into output_dir/static/ with the first HASH_LENGTH hex characters of its SHA-256 in the
+//! file name, "/static/css/site.css" becomes "/static/css/site.1f2e3d4c5b6a7988.css".  url() references in
+//! CSS files, relative ones such as url(../img/bg.png) included, are rewritten before the CSS is hashed so a
+//! changed image changes the stylesheet hash too.  The mapping is written to output_dir/static/manifest.json.
+//!
+//! Once a manifest exists pages and menus use the fingerprinted paths: includes, icons and quoted or url()
+//! references in rendered bodies are rewritten.  Meta files keep the original paths, which is also what the
+//! checker looks for.  With bundle_assets on the build also writes per page css and javascript bundles, see
+//! bundles.  With images enabled processed images also get resized copies and thumbnails, see images.  The
+//! attachments of page bundles are copied as they are, see page_bundles.
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+use serde_derive::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::bundles::{apply_bundle, build_bundles, AssetBundle};
+use crate::check::normalize_link;
+use crate::images::{build_images, rewrite_images, ImageEntry};
+use crate::page_bundles::copy_bundle_attachments;
+use crate::{load_config, static_webpath_to_localpath, PageContent};
+
+pub const HASH_LENGTH: usize = 16;
+pub const MANIFEST_WEBPATH: &str = "/static/manifest.json";
+
+/// Where a static file ended up
+///
+/// path
+///     Fingerprinted web path
+/// sha256
+///     Full hex digest of the written file
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ManifestEntry {
+    pub path: String,
+    pub sha256: String,
+    pub size: u64,
+}
+
+/// Original web paths mapped to their fingerprinted copies
+///
+/// bundles
+///     bundles::bundle_key() of an EffectiveAssets mapped to the bundles built for it
+/// images
+///     Original web paths of processed images mapped to their resized copies
+/// attachments
+///     Web paths of the page bundle attachments copied to the build, not fingerprinted
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct AssetManifest {
+    pub assets: BTreeMap<String, ManifestEntry>,
+    #[serde(default)]
+    pub bundles: BTreeMap<String, AssetBundle>,
+    #[serde(default)]
+    pub images: BTreeMap<String, ImageEntry>,
+    #[serde(default)]
+    pub attachments: Vec<String>,
+}
+
+impl AssetManifest {
+    /// Fingerprinted web path of an original one, None if it isn't a built asset
+    pub fn lookup(&self, webpath: &str) -> Option<&str> {
+        self.assets.get(webpath).map(|x| x.path.as_str())
+    }
+}
+
+/// Hex SHA-256 of some bytes
+pub fn fingerprint(bytes: &[u8]) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(bytes);
+    hasher
+        .finalize()
+        .iter()
+        .map(|x| format!("{:02x}", x))
+        .collect()
+}
+
+/// Put the start of a hash before the extension of a web path, or on the end if there isn't one
+pub fn fingerprinted_path(webpath: &str, hash: &str) -> String {
+    insert_before_extension(webpath, &hash[..HASH_LENGTH.min(hash.len())])
+}
+
+/// "/a/b.png" with "x" is "/a/b.x.png", a name without an extension gets it on the end
+pub fn insert_before_extension(webpath: &str, insert: &str) -> String {
+    let name_start = webpath.rfind('/').map(|x| x + 1).unwrap_or(0);
+    match webpath[name_start..].rfind('.') {
+        Some(dot) if dot > 0 => {
+            let dot = name_start + dot;
+            format!("{}.{}{}", &webpath[..dot], insert, &webpath[dot..])
+        }
+        _ => format!("{}.{}", webpath, insert),
+    }
+}
+
+/// Swap manifest paths in quoted attribute values and CSS url() references
+///
+/// Parameters:
+///     text(&str), HTML or CSS
+///     manifest(&AssetManifest), the built assets
+/// Returns:
+///     String, the text with every exact reference to a built asset replaced
+pub fn rewrite_references(text: &str, manifest: &AssetManifest) -> String {
+    let mut rewritten = text.to_string();
+    for (original, entry) in &manifest.assets {
+        if !rewritten.contains(original.as_str()) {
+            continue;
+        }
+        for (open, close) in &[("\"", "\""), ("'", "'"), ("url(", ")")] {
+            rewritten = rewritten.replace(
+                &format!("{}{}{}", open, original, close),
+                &format!("{}{}{}", open, entry.path, close),
+            );
+        }
+    }
+    rewritten
+}
+
+/// Point the url() references of a stylesheet at the fingerprinted assets, relative ones are resolved against
+/// the stylesheet's own web path and written out absolute
+///
+/// Parameters:
+///     css(&str), the stylesheet source
+///     stylesheet_webpath(&str), web path of the stylesheet such as "/static/css/site.css"
+///     manifest(&AssetManifest), the assets built so far
+/// Returns:
+///     String, the stylesheet with every reference to a built asset replaced
+pub fn rewrite_stylesheet_references(
+    css: &str,
+    stylesheet_webpath: &str,
+    manifest: &AssetManifest,
+) -> String {
+    let mut rewritten = String::new();
+    let mut rest = css;
+    while let Some(start) = rest.find("url(") {
+        let value_start = start + "url(".len();
+        let value_end = match rest[value_start..].find(')') {
+            Some(val) => value_start + val,
+            None => break,
+        };
+        rewritten.push_str(&rest[..value_start]);
+        let value = &rest[value_start..value_end];
+        let reference = value.trim().trim_matches(|c| c == '"' || c == '\'');
+        let suffix_start = reference.find(['?', '#']).unwrap_or(reference.len());
+        let built = match normalize_link(stylesheet_webpath, reference) {
+            Some(target) if !reference.starts_with('/') => manifest.lookup(&target),
+            _ => None, // Absolute references are left to rewrite_references()
+        };
+        match built {
+            Some(path) => rewritten.push_str(&format!("{}{}", path, &reference[suffix_start..])),
+            None => rewritten.push_str(value),
+        }
+        rest = &rest[value_end..];
+    }
+    rewritten.push_str(rest);
+    rewrite_references(&rewritten, manifest)
+}
+
+/// Copy the static directory into the build output with fingerprinted names and write the manifest
+///
+/// Returns:
+///     AssetManifest, what was written
+pub fn build_static_assets() -> AssetManifest {
+    let config = load_config();
+    if config.output_dir.is_empty() {
+        panic!("Set output_dir in the config to build static assets.");
+    }
+    let output_dir = config.output_dir.trim_end_matches('/').to_string();
+    let source_dir = static_webpath_to_localpath("/static");
+
+    let mut webpaths: Vec<String> = Vec::new();
+    collect_static_files(&source_dir, "/static", &mut webpaths);
+    webpaths.sort();
+    // Everything a stylesheet can point at has to be fingerprinted before the stylesheet is
+    let (css, other): (Vec<String>, Vec<String>) =
+        webpaths.into_iter().partition(|x| x.ends_with(".css"));
+
+    let mut manifest = AssetManifest::default();
+    for webpath in other.into_iter().chain(css) {
+        let source_path = static_webpath_to_localpath(&webpath);
+        let mut bytes = match fs::read(&source_path) {
+            Err(why) => panic!("Couldn't read {}: {}", source_path.to_string_lossy(), why),
+            Ok(val) => val,
+        };
+        if webpath.ends_with(".css") {
+            let text = String::from_utf8_lossy(&bytes).to_string();
+            bytes = rewrite_stylesheet_references(&text, &webpath, &manifest).into_bytes();
+        }
+        let sha256 = fingerprint(&bytes);
+        let path = fingerprinted_path(&webpath, &sha256);
+        write_output(&PathBuf::from(format!("{}{}", output_dir, path)), &bytes);
+        manifest.assets.insert(
+            webpath,
+            ManifestEntry {
+                path,
+                sha256,
+                size: bytes.len() as u64,
+            },
+        );
+    }
+
+    if config.images.enabled {
+        build_images(&mut manifest, &output_dir, &config.images);
+    }
+    manifest.attachments = copy_bundle_attachments(&output_dir);
+    if config.bundle_assets {
+        build_bundles(&mut manifest, &output_dir);
+    }
+
+    let serialized = match serde_json::to_string_pretty(&manifest) {
+        Err(why) => panic!("Serialize to json fail: {}", why),
+        Ok(val) => val,
+    };
+    write_output(
+        &PathBuf::from(format!("{}{}", output_dir, MANIFEST_WEBPATH)),
+        serialized.as_bytes(),
+    );
+    manifest
+}
+
+fn collect_static_files(dir: &Path, dir_webpath: &str, webpaths: &mut Vec<String>) {
+    let entries = match fs::read_dir(dir) {
+        Err(_) => return, // No static directory, nothing to build
+        Ok(val) => val,
+    };
+    for entry in entries.flatten() {
+        let name = entry.file_name().to_string_lossy().to_string();
+        if name.starts_with('.') {
+            continue;
+        }
+        let webpath = format!("{}/{}", dir_webpath, name);
+        if entry.path().is_dir() {
+            collect_static_files(&entry.path(), &webpath, webpaths);
+        } else if webpath != MANIFEST_WEBPATH {
+            webpaths.push(webpath);
+        }
+    }
+}
+
+fn write_output(path: &Path, bytes: &[u8]) {
+    if let Some(parent) = path.parent() {
+        if let Err(why) = fs::create_dir_all(parent) {
+            panic!("Couldn't create {}: {}", parent.to_string_lossy(), why);
+        }
+    }
+    if let Err(why) = fs::write(path, bytes) {
+        panic!("Couldn't write {}: {}", path.to_string_lossy(), why);
+    }
+}
+
+// The manifest of the last build with the modified time it was read at, reloaded when the file changes
+static MANIFEST_CACHE: Mutex<Option<(SystemTime, Arc<AssetManifest>)>> = Mutex::new(None);
+
+/// The manifest of the last asset build, None if there hasn't been one.  It's shared, not copied, so looking
+/// assets up for every menu item stays cheap.
+pub fn load_asset_manifest() -> Option<Arc<AssetManifest>> {
+    let output_dir = load_config().output_dir;
+    if output_dir.is_empty() {
+        return None;
+    }
+    let path = PathBuf::from(format!(
+        "{}{}",
+        output_dir.trim_end_matches('/'),
+        MANIFEST_WEBPATH
+    ));
+    let modified = fs::metadata(&path).and_then(|x| x.modified()).ok()?;
+
+    let mut cache = match MANIFEST_CACHE.lock() {
+        Err(why) => panic!("Asset manifest cache lock poisoned: {}", why),
+        Ok(val) => val,
+    };
+    if let Some((cached_at, manifest)) = cache.as_ref() {
+        if *cached_at == modified {
+            return Some(Arc::clone(manifest));
+        }
+    }
+    let manifest: AssetManifest = match serde_json::from_str(&fs::read_to_string(&path).ok()?) {
+        Err(why) => {
+            println!("Asset manifest is invalid, using original paths: {}", why); // TODO Change to logging
+            return None;
+        }
+        Ok(val) => val,
+    };
+    let manifest = Arc::new(manifest);
+    *cache = Some((modified, Arc::clone(&manifest)));
+    Some(manifest)
+}
+
+/// Fingerprinted web path of a static asset when it has been built, the path unchanged otherwise
+pub fn asset_url(webpath: &str) -> String {
+    match load_asset_manifest()
+        .as_ref()
+        .and_then(|x| x.lookup(webpath))
+    {
+        Some(val) => val.to_string(),
+        None => webpath.to_string(),
+    }
+}
+
+/// Is a web path a file written by the last asset build, fingerprinted copies, resized images and bundles
+pub fn is_built_asset(webpath: &str) -> bool {
+    let output_dir = load_config().output_dir;
+    !output_dir.is_empty()
+        && PathBuf::from(format!("{}{}", output_dir.trim_end_matches('/'), webpath)).is_file()
+}
+
+/// Point a page's includes, icon and body references at the fingerprinted assets, if they have been built
+pub fn rewrite_page_assets(page_content: &mut PageContent) {
+    let manifest = match load_asset_manifest() {
+        Some(val) => val,
+        None => return,
+    };
+    apply_bundle(&mut page_content.assets, &manifest);
+    let rewrite_list = |list: &mut Vec<String>| {
+        for item in list.iter_mut() {
+            if let Some(path) = manifest.lookup(item) {
+                *item = path.to_string();
+            }
+        }
+    };
+    rewrite_list(&mut page_content.assets.javascript_include);
+    rewrite_list(&mut page_content.assets.css_include);
+    if let Some(image) = manifest.images.get(&page_content.meta.content_icon) {
+        page_content.meta.content_icon = image.thumbnail.clone();
+    } else if let Some(path) = manifest.lookup(&page_content.meta.content_icon) {
+        page_content.meta.content_icon = path.to_string();
+    }
+
+    let sizes = load_config().images.sizes;
+    let rewrite_body =
+        |body: &str| rewrite_references(&rewrite_images(body, &manifest, &sizes), &manifest);
+    page_content.markdown.body = rewrite_body(&page_content.markdown.body);
+    if let Some(html) = &mut page_content.html {
+        html.body = rewrite_body(&html.body);
+    }
+    if let Some(rendered) = page_content.json.as_mut().and_then(|x| x.rendered.as_mut()) {
+        *rendered = rewrite_body(rendered);
+    }
+    for formatted in &mut page_content.formatted {
+        formatted.body = rewrite_body(&formatted.body);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fingerprints_file_names() {
+        let hash = fingerprint(b"body{}");
+        assert_eq!(hash.len(), 64);
+        assert_eq!(
+            fingerprinted_path("/static/css/site.min.css", &hash),
+            format!("/static/css/site.min.{}.css", &hash[..HASH_LENGTH])
+        );
+        assert_eq!(
+            fingerprinted_path("/static/LICENSE", "abc"),
+            "/static/LICENSE.abc"
+        );
+        assert_eq!(
+            fingerprinted_path("/static/.well/known", "abc"),
+            "/static/.well/known.abc"
+        );
+    }
+
+    #[test]
+    fn rewrites_exact_references() {
+        let mut manifest = AssetManifest::default();
+        manifest.assets.insert(
+            String::from("/static/a.png"),
+            ManifestEntry {
+                path: String::from("/static/a.123.png"),
+                sha256: String::from("123"),
+                size: 1,
+            },
+        );
+        assert_eq!(
+            rewrite_references(
+                "<img src=\"/static/a.png\"><a href='/static/a.png.txt'>",
+                &manifest
+            ),
+            "<img src=\"/static/a.123.png\"><a href='/static/a.png.txt'>"
+        );
+        assert_eq!(
+            rewrite_references("div{background:url(/static/a.png)}", &manifest),
+            "div{background:url(/static/a.123.png)}"
+        );
+    }
+
+    #[test]
+    fn rewrites_relative_stylesheet_references() {
+        let mut manifest = AssetManifest::default();
+        manifest.assets.insert(
+            String::from("/static/img/bg.png"),
+            ManifestEntry {
+                path: String::from("/static/img/bg.123.png"),
+                sha256: String::from("123"),
+                size: 1,
+            },
+        );
+        let css = "a{background:url(../img/bg.png)} b{background:url('../img/bg.png?v=2')} \
+                   c{background:url(/static/img/bg.png)} d{background:url(missing.png)} \
+                   e{background:url(data:image/png;base64,xyz)}";
+        assert_eq!(
+            rewrite_stylesheet_references(css, "/static/css/site.css", &manifest),
+            "a{background:url(/static/img/bg.123.png)} b{background:url(/static/img/bg.123.png?v=2)} \
+             c{background:url(/static/img/bg.123.png)} d{background:url(missing.png)} \
+             e{background:url(data:image/png;base64,xyz)}"
+        );
+    }
+}
@@ -0,0 +1,172 @@
+//! Reverse link graph
+//!
+//! Renders the site once and records, for each page, which other pages reference it through a link in their
+//! body or an entry in their content_list, globs and queries count for every page they expand to.  Building
+//! the index reads every page so do it once per build or server start and hand the result around.
+use std::collections::HashMap;
+
+use serde_derive::{Deserialize, Serialize};
+
+use crate::check::{extract_links, normalize_link};
+use crate::content_list::{expand_content_list, parse_entry, ContentListEntry};
+use crate::{
+    generate_content_state, read_only_content, read_single_page, tree_to_webpaths, PageContent,
+};
+
+/// One page referencing another
+///
+/// path
+///     Web path of the referencing page
+/// title
+///     Title of the referencing page from its content meta
+/// via
+///     How it references the page: "link" or "content_list"
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Backlink {
+    pub path: String,
+    pub title: String,
+    pub via: String,
+}
+
+/// Map of target web path to the pages referencing it
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct BacklinkIndex {
+    pub backlinks: HashMap<String, Vec<Backlink>>,
+}
+
+impl BacklinkIndex {
+    /// Backlinks for a web path, empty if nothing references it
+    pub fn for_page(&self, webpath: &str) -> Vec<Backlink> {
+        match self.backlinks.get(&normalize_target(webpath)) {
+            Some(val) => val.clone(),
+            None => Vec::new(),
+        }
+    }
+
+    /// Fill in PageContent.backlinks for a page loaded with read_single_page()
+    ///
+    /// Parameters:
+    ///     webpath(&str), the web path the page was loaded with, meta.path can be stale
+    ///     page_content(&mut PageContent), the loaded page
+    pub fn attach(&self, webpath: &str, page_content: &mut PageContent) {
+        page_content.backlinks = self.for_page(webpath);
+    }
+
+    fn insert(&mut self, target: String, backlink: Backlink) {
+        if target == backlink.path {
+            return; // Pages linking to themselves are not interesting
+        }
+        let entries = self.backlinks.entry(target).or_default();
+        if !entries.contains(&backlink) {
+            entries.push(backlink);
+        }
+    }
+}
+
+/// Build the backlink index for the whole content directory
+pub fn generate_backlinks() -> BacklinkIndex {
+    let dir_tree = generate_content_state();
+    let mut index = BacklinkIndex::default();
+    let _read_only = read_only_content();
+
+    for webpath in tree_to_webpaths(&dir_tree) {
+        let page = read_single_page(webpath.clone());
+        for (target, via) in outgoing_links(&webpath, &page) {
+            index.insert(
+                target,
+                Backlink {
+                    path: webpath.clone(),
+                    title: page.meta.title.clone(),
+                    via: via.to_string(),
+                },
+            );
+        }
+    }
+
+    for entries in index.backlinks.values_mut() {
+        entries.sort_by(|a, b| a.path.cmp(&b.path));
+    }
+    index
+}
+
+/// Every internal web path a rendered page points at along with how it points there
+pub fn outgoing_links(webpath: &str, page: &PageContent) -> Vec<(String, &'static str)> {
+    let mut links: Vec<(String, &'static str)> = Vec::new();
+
+    let mut bodies: Vec<&String> = vec![&page.markdown.body];
+    if let Some(html) = &page.html {
+        bodies.push(&html.body);
+    }
+    for formatted in &page.formatted {
+        bodies.push(&formatted.body);
+    }
+    for body in bodies {
+        for target in extract_links(body) {
+            if let Some(normalized) = normalize_link(webpath, &target) {
+                links.push((normalized, "link"));
+            }
+        }
+    }
+    for item in &page.meta.content_list {
+        match parse_entry(item) {
+            Ok(ContentListEntry::Path(webpath)) => {
+                links.push((normalize_target(&webpath), "content_list"))
+            }
+            Ok(_) => {
+                // Globs and queries reference whatever they currently expand to
+                let (webpaths, _) = expand_content_list(std::slice::from_ref(item));
+                for webpath in webpaths {
+                    links.push((normalize_target(&webpath), "content_list"));
+                }
+            }
+            Err(_) => {}
+        }
+    }
+
+    links
+}
+
+fn normalize_target(webpath: &str) -> String {
+    match normalize_link("/", webpath) {
+        Some(val) => val,
+        None => webpath.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn indexes_and_attaches_backlinks() {
+        let mut page = PageContent::default();
+        page.markdown.body = String::from(
+            "<a href=\"second/\">Second</a> <a href=\"/blog/first#top\">Self</a> \
+             <a href=\"https://example.com/\">Out</a>",
+        );
+        let links = outgoing_links("/blog/first", &page);
+        assert_eq!(
+            links,
+            vec![
+                (String::from("/blog/second"), "link"),
+                (String::from("/blog/first"), "link")
+            ]
+        );
+
+        let mut index = BacklinkIndex::default();
+        let backlink = Backlink {
+            path: String::from("/blog/first"),
+            title: String::from("First"),
+            via: String::from("link"),
+        };
+        index.insert(String::from("/blog/second"), backlink.clone());
+        index.insert(String::from("/blog/second"), backlink.clone());
+        index.insert(String::from("/blog/first"), backlink.clone());
+        assert!(index.for_page("/blog/first").is_empty());
+
+        let mut second = PageContent::default();
+        second.meta.path = String::from("/old/second");
+        index.attach("/blog/second/", &mut second);
+        assert_eq!(second.backlinks, vec![backlink]);
+    }
+}
@@ -0,0 +1,363 @@
+//! CSS and javascript bundles
+//!
+//! With bundle_assets on in the config, build_static_assets() also walks every page, takes its
+//! EffectiveAssets and concatenates the local /static/ includes followed by the inline code into one minified
+//! css and one minified javascript file.  Bundles are named by their content hash so pages with the same
+//! asset set share a file.  The manifest maps a hash of the page's EffectiveAssets to its bundles, and
+//! rewrite_page_assets() swaps the bundled includes and inline code for the bundle.
+//!
+//! Includes that aren't local static files (other hosts, missing files) stay separate includes, loaded before
+//! the bundle.  The minifiers are conservative: comments and redundant whitespace go, nothing is renamed.
+//! Javascript keeps its line breaks so automatic semicolon insertion still works.  A "/" after an operator,
+//! an opening bracket or a keyword like return starts a regular expression literal, which is copied verbatim
+//! like strings are, anywhere else it's a division.
+use std::fs;
+
+use serde_derive::{Deserialize, Serialize};
+
+use crate::assets::{
+    fingerprint, fingerprinted_path, rewrite_stylesheet_references, AssetManifest,
+};
+use crate::{
+    content_local_path, generate_content_state, read_content_meta, read_only_content,
+    read_section_meta, static_webpath_to_localpath, tree_to_webpaths, EffectiveAssets,
+};
+
+pub const CSS_BUNDLE_WEBPATH: &str = "/static/bundles/bundle.css";
+pub const JAVASCRIPT_BUNDLE_WEBPATH: &str = "/static/bundles/bundle.js";
+
+/// The bundles for one asset set, empty when there was nothing to bundle
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct AssetBundle {
+    pub css: String,
+    pub javascript: String,
+}
+
+/// Key of an asset set in AssetManifest.bundles
+pub fn bundle_key(assets: &EffectiveAssets) -> String {
+    let serialized = match serde_json::to_string(assets) {
+        Err(why) => panic!("Serialize to json fail: {}", why),
+        Ok(val) => val,
+    };
+    fingerprint(serialized.as_bytes())
+}
+
+/// Is an include a static file that can go in a bundle
+fn bundleable(include: &str) -> bool {
+    include.starts_with("/static/") && static_webpath_to_localpath(include).is_file()
+}
+
+/// Build the bundles of every page and record them in the manifest
+///
+/// Parameters:
+///     manifest(&mut AssetManifest), the fingerprinted static assets, css url() references are rewritten with it
+///     output_dir(&str), the build output directory
+pub fn build_bundles(manifest: &mut AssetManifest, output_dir: &str) {
+    let _read_only = read_only_content(); // Building never writes default metafiles
+    for webpath in tree_to_webpaths(&generate_content_state()) {
+        let meta = read_content_meta(&content_local_path(&webpath));
+        let assets = meta.effective_assets(&read_section_meta(&webpath));
+        let key = bundle_key(&assets);
+        if manifest.bundles.contains_key(&key) {
+            continue; // Same asset set as a page already done
+        }
+
+        let mut css = String::new();
+        for include in assets.css_include.iter().filter(|x| bundleable(x)) {
+            let source = read_static_text(include);
+            // The bundle lives in another directory, relative references have to be made absolute
+            css.push_str(&minify_css(&rewrite_stylesheet_references(
+                &source, include, manifest,
+            )));
+            css.push('\n');
+        }
+        css.push_str(&minify_css(&assets.css_inline));
+        let mut javascript = String::new();
+        for include in assets.javascript_include.iter().filter(|x| bundleable(x)) {
+            javascript.push_str(&minify_javascript(&read_static_text(include)));
+            javascript.push_str(";\n"); // Files that don't end in a semicolon can't run into the next
+        }
+        javascript.push_str(&minify_javascript(&assets.javascript_inline));
+
+        let bundle = AssetBundle {
+            css: write_bundle(CSS_BUNDLE_WEBPATH, css.trim(), output_dir),
+            javascript: write_bundle(JAVASCRIPT_BUNDLE_WEBPATH, javascript.trim(), output_dir),
+        };
+        manifest.bundles.insert(key, bundle);
+    }
+}
+
+fn read_static_text(include: &str) -> String {
+    let path = static_webpath_to_localpath(include);
+    match fs::read_to_string(&path) {
+        Err(why) => panic!("Couldn't read {}: {}", path.to_string_lossy(), why),
+        Ok(val) => val,
+    }
+}
+
+/// Write a bundle under its content hash, returns its web path or "" for an empty bundle
+fn write_bundle(base_webpath: &str, content: &str, output_dir: &str) -> String {
+    if content.is_empty() {
+        return String::new();
+    }
+    let webpath = fingerprinted_path(base_webpath, &fingerprint(content.as_bytes()));
+    let path = std::path::PathBuf::from(format!("{}{}", output_dir, webpath));
+    if let Some(parent) = path.parent() {
+        if let Err(why) = fs::create_dir_all(parent) {
+            panic!("Couldn't create {}: {}", parent.to_string_lossy(), why);
+        }
+    }
+    if let Err(why) = fs::write(&path, content) {
+        panic!("Couldn't write {}: {}", path.to_string_lossy(), why);
+    }
+    webpath
+}
+
+/// Swap the bundled includes and inline code of a page for its bundles, if the manifest has them
+pub fn apply_bundle(assets: &mut EffectiveAssets, manifest: &AssetManifest) {
+    let bundle = match manifest.bundles.get(&bundle_key(assets)) {
+        Some(val) => val,
+        None => return,
+    };
+    assets.css_include.retain(|x| !bundleable(x));
+    assets.css_inline = String::new();
+    if !bundle.css.is_empty() {
+        assets.css_include.push(bundle.css.clone());
+    }
+    assets.javascript_include.retain(|x| !bundleable(x));
+    assets.javascript_inline = String::new();
+    if !bundle.javascript.is_empty() {
+        assets.javascript_include.push(bundle.javascript.clone());
+    }
+}
+
+/// Strip comments and whitespace from CSS, strings are left alone
+pub fn minify_css(css: &str) -> String {
+    let chars: Vec<char> = css.chars().collect();
+    let mut minified = String::new();
+    let mut pending_space = false;
+    let mut index = 0;
+    while index < chars.len() {
+        let c = chars[index];
+        if c == '/' && chars.get(index + 1) == Some(&'*') {
+            index = match find_from(&chars, index + 2, &['*', '/']) {
+                Some(end) => end + 2,
+                None => chars.len(),
+            };
+            continue;
+        }
+        if c.is_whitespace() {
+            pending_space = true;
+            index += 1;
+            continue;
+        }
+        if pending_space {
+            let after_punctuation = match minified.chars().last() {
+                Some(last) => "{}:;,>(".contains(last),
+                None => true,
+            };
+            if !after_punctuation && !"{};,>)".contains(c) {
+                minified.push(' ');
+            }
+            pending_space = false;
+        }
+        if c == '"' || c == '\'' {
+            index = copy_string(&chars, index, &mut minified);
+            continue;
+        }
+        if c == '}' && minified.ends_with(';') {
+            minified.pop(); // The last declaration doesn't need one
+        }
+        minified.push(c);
+        index += 1;
+    }
+    minified
+}
+
+/// Strip comments, indentation, trailing whitespace and blank lines from javascript, strings, template
+/// literals and regular expression literals are left alone
+pub fn minify_javascript(javascript: &str) -> String {
+    let chars: Vec<char> = javascript.chars().collect();
+    let mut minified = String::new();
+    let mut index = 0;
+    while index < chars.len() {
+        let c = chars[index];
+        let next = chars.get(index + 1);
+        if c == '/' && next == Some(&'*') {
+            index = match find_from(&chars, index + 2, &['*', '/']) {
+                Some(end) => end + 2,
+                None => chars.len(),
+            };
+            continue;
+        }
+        if c == '/' && next == Some(&'/') {
+            index = find_from(&chars, index, &['\n']).unwrap_or(chars.len());
+            continue;
+        }
+        if c == '/' && regex_can_start(&minified) {
+            index = copy_regex(&chars, index, &mut minified);
+            continue;
+        }
+        match c {
+            '"' | '\'' | '`' => {
+                index = copy_string(&chars, index, &mut minified);
+                continue;
+            }
+            '\\' => {
+                minified.push(c);
+                if let Some(escaped) = next {
+                    minified.push(*escaped);
+                }
+                index += 2;
+                continue;
+            }
+            '\n' => {
+                while minified.ends_with(' ')
+                    || minified.ends_with('\t')
+                    || minified.ends_with('\r')
+                {
+                    minified.pop();
+                }
+                if !minified.is_empty() && !minified.ends_with('\n') {
+                    minified.push('\n');
+                }
+            }
+            c if c.is_whitespace() && (minified.is_empty() || minified.ends_with('\n')) => {}
+            c => minified.push(c),
+        }
+        index += 1;
+    }
+    minified.trim_end().to_string()
+}
+
+/// Can a "/" after the javascript written so far start a regular expression literal rather than a division
+fn regex_can_start(minified: &str) -> bool {
+    let before = minified.trim_end();
+    let last = match before.chars().last() {
+        Some(val) => val,
+        None => return true,
+    };
+    if "(,=:[!&|?{};+-*%<>~^".contains(last) {
+        return true;
+    }
+    let word: String = before
+        .chars()
+        .rev()
+        .take_while(|x| x.is_alphanumeric() || *x == '_' || *x == '$')
+        .collect::<Vec<char>>()
+        .into_iter()
+        .rev()
+        .collect();
+    [
+        "return",
+        "typeof",
+        "instanceof",
+        "in",
+        "of",
+        "new",
+        "delete",
+        "void",
+        "throw",
+        "case",
+        "do",
+        "else",
+        "yield",
+        "await",
+    ]
+    .contains(&word.as_str())
+}
+
+/// Copy a regular expression literal starting at index verbatim, returns the index after its closing slash
+///
+/// A "/" inside a character class doesn't close it.  The flags are copied as ordinary code.
+fn copy_regex(chars: &[char], index: usize, minified: &mut String) -> usize {
+    minified.push('/');
+    let mut index = index + 1;
+    let mut in_class = false;
+    while index < chars.len() {
+        let c = chars[index];
+        if c == '\n' {
+            break; // Not a valid literal, leave the rest to the caller
+        }
+        minified.push(c);
+        index += 1;
+        match c {
+            '\\' => {
+                if let Some(escaped) = chars.get(index) {
+                    minified.push(*escaped);
+                }
+                index += 1;
+            }
+            '[' => in_class = true,
+            ']' => in_class = false,
+            '/' if !in_class => break,
+            _ => {}
+        }
+    }
+    index
+}
+
+/// Index of the first occurrence of a char sequence at or after start
+fn find_from(chars: &[char], start: usize, needle: &[char]) -> Option<usize> {
+    (start..chars.len()).find(|x| chars[*x..].starts_with(needle))
+}
+
+/// Copy a quoted string starting at index verbatim, returns the index after its closing quote
+fn copy_string(chars: &[char], index: usize, minified: &mut String) -> usize {
+    let quote = chars[index];
+    minified.push(quote);
+    let mut index = index + 1;
+    while index < chars.len() {
+        let c = chars[index];
+        minified.push(c);
+        if c == '\\' {
+            if let Some(escaped) = chars.get(index + 1) {
+                minified.push(*escaped);
+            }
+            index += 2;
+            continue;
+        }
+        index += 1;
+        if c == quote {
+            break;
+        }
+    }
+    index
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn minifies_css() {
+        let css = "/* site */\nbody {\n  color: red;\n  font-family: \"Open  Sans\";\n}\n\ndiv :hover , a > b { margin: 0 auto; }\n";
+        assert_eq!(
+            minify_css(css),
+            "body{color:red;font-family:\"Open  Sans\"}div :hover,a>b{margin:0 auto}"
+        );
+    }
+
+    #[test]
+    fn minifies_javascript() {
+        let javascript = "// setup\nvar a = 1; /* one */\n\n    var url = \"http://x\"; // trailing\nvar t = `a\n    b`;\nvar r = /\\/\\//;\n";
+        assert_eq!(
+            minify_javascript(javascript),
+            "var a = 1;\nvar url = \"http://x\";\nvar t = `a\n    b`;\nvar r = /\\/\\//;"
+        );
+        let javascript = "s = s.replace(/'/g, \"&#39;\");\nvar base = 'https://example.com/';\nvar c = /[/]/.test(s) ? a / b : 0; // end\n";
+        assert_eq!(
+            minify_javascript(javascript),
+            "s = s.replace(/'/g, \"&#39;\");\nvar base = 'https://example.com/';\nvar c = /[/]/.test(s) ? a / b : 0;"
+        );
+    }
+
+    #[test]
+    fn keys_differ_by_asset_set() {
+        let mut assets = EffectiveAssets::default();
+        let empty = bundle_key(&assets);
+        assets.css_inline = String::from("a{}");
+        assert_ne!(bundle_key(&assets), empty);
+        assert_eq!(bundle_key(&EffectiveAssets::default()), empty);
+    }
+}
@@ -0,0 +1,436 @@
+//! Site wide broken link checker
+//!
+//! Renders every page found by generate_content_state() and reports links, content lists, icons and
+//! javascript/css includes that don't resolve to existing content or static files.
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde_derive::{Deserialize, Serialize};
+
+use crate::assets::is_built_asset;
+use crate::content_list::{parse_entry, ContentListEntry};
+use crate::languages::{site_languages, split_language_prefix_in};
+use crate::pagination::split_page_path;
+use crate::shortcodes::{shortcode_problem, shortcodes_in};
+use crate::taxonomy::{generate_taxonomy_index, TaxonomyIndex, TAXONOMY_CATEGORIES, TAXONOMY_TAGS};
+use crate::{
+    content_local_path, content_path_with_extension, does_content_exist, does_directory_exist,
+    generate_content_state, read_menu_meta_file, read_only_content, read_single_page,
+    static_webpath_to_localpath, tree_to_webpaths, webpath_to_localpath,
+};
+use file_tree::DirTree;
+
+/// A single unresolved reference found by the checker
+///
+/// file
+///     Local path of the file the reference was found in
+/// line
+///     1 based line number of the reference in that file, 0 if it couldn't be located
+/// kind
+///     What sort of reference it is: link, content_list, content_icon, menu_icon, javascript_include, css_include,
+///     shortcode
+/// target
+///     The reference exactly as written
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BrokenLink {
+    pub file: String,
+    pub line: usize,
+    pub kind: String,
+    pub target: String,
+}
+
+/// Check every page and section in the content directory
+///
+/// Returns:
+///     Vec<BrokenLink>, empty if everything resolves
+pub fn check_site() -> Vec<BrokenLink> {
+    let dir_tree = generate_content_state();
+    let mut broken: Vec<BrokenLink> = Vec::new();
+
+    for webpath in tree_to_webpaths(&dir_tree) {
+        broken.append(&mut check_page(&webpath));
+    }
+    broken.append(&mut check_menu_metas(&dir_tree));
+
+    broken
+}
+
+/// Render a single page and check its links, content_list, icon and includes
+///
+/// Parameters:
+///     webpath(&str), web path of the page such as "/blog/first_post"
+/// Returns:
+///     Vec<BrokenLink>, the unresolved references of this page
+pub fn check_page(webpath: &str) -> Vec<BrokenLink> {
+    let _read_only = read_only_content(); // Checking never writes default metafiles
+    let page = read_single_page(webpath.to_string());
+    let local_path = content_local_path(webpath);
+    let mut broken: Vec<BrokenLink> = Vec::new();
+
+    let markdown_path = content_path_with_extension(&local_path, "md");
+    if markdown_path.exists() {
+        for target in extract_links(&page.markdown.body) {
+            if !link_resolves(webpath, &target) {
+                broken.push(broken_link(&markdown_path, "link", &target));
+            }
+        }
+        // Shortcodes that fail only leave a comment in the page, so check them at the source
+        let source = fs::read_to_string(&markdown_path).unwrap_or_default();
+        for shortcode in shortcodes_in(&source) {
+            let problem = match &shortcode {
+                Ok(parsed) => shortcode_problem(webpath, parsed),
+                Err(why) => Some((String::new(), why.clone())),
+            };
+            if let Some((target, _)) = problem {
+                // A missing section is easier to find by its name than by the page
+                let needle = target.rsplit('#').next().unwrap_or("").to_string();
+                broken.push(BrokenLink {
+                    line: find_line(&markdown_path, &needle),
+                    ..broken_link(&markdown_path, "shortcode", &target)
+                });
+            }
+        }
+    }
+    if let Some(html) = &page.html {
+        let html_path = content_path_with_extension(&local_path, "html");
+        for target in extract_links(&html.body) {
+            if !link_resolves(webpath, &target) {
+                broken.push(broken_link(&html_path, "link", &target));
+            }
+        }
+    }
+    for formatted in &page.formatted {
+        let formatted_path = content_path_with_extension(&local_path, &formatted.extension);
+        for target in extract_links(&formatted.body) {
+            if !link_resolves(webpath, &target) {
+                broken.push(broken_link(&formatted_path, "link", &target));
+            }
+        }
+    }
+
+    let meta_path = content_path_with_extension(&local_path, "content_meta");
+    for item in &page.meta.content_list {
+        let resolves = match parse_entry(item) {
+            Ok(ContentListEntry::Path(webpath)) => {
+                does_content_exist(webpath.clone()) || does_directory_exist(webpath)
+            }
+            Ok(_) => true, // A glob or query matching nothing is an empty list, not a broken one
+            Err(_) => false,
+        };
+        if !resolves {
+            broken.push(broken_link(&meta_path, "content_list", item));
+        }
+    }
+    if !asset_exists(&page.meta.content_icon) {
+        broken.push(broken_link(
+            &meta_path,
+            "content_icon",
+            &page.meta.content_icon,
+        ));
+    }
+    for include in &page.meta.javascript_include {
+        if !asset_exists(include) {
+            broken.push(broken_link(&meta_path, "javascript_include", include));
+        }
+    }
+    for include in &page.meta.css_include {
+        if !asset_exists(include) {
+            broken.push(broken_link(&meta_path, "css_include", include));
+        }
+    }
+
+    broken
+}
+
+/// Check the icon and includes of every .menu_meta file in the tree, directories without one are skipped
+pub fn check_menu_metas(dir_tree: &DirTree) -> Vec<BrokenLink> {
+    let mut broken: Vec<BrokenLink> = Vec::new();
+
+    for sub_tree in dir_tree.directories.values() {
+        let meta_path = PathBuf::from(format!(
+            "{}.menu_meta",
+            sub_tree.absolute_path.trim_end_matches("/")
+        ));
+        if meta_path.exists() {
+            let menu_meta = read_menu_meta_file(meta_path.clone());
+            if !asset_exists(&menu_meta.menu_icon) {
+                broken.push(broken_link(&meta_path, "menu_icon", &menu_meta.menu_icon));
+            }
+            for include in menu_meta
+                .section_javascript_include
+                .iter()
+                .chain(menu_meta.javascript_include.iter())
+            {
+                if !asset_exists(include) {
+                    broken.push(broken_link(&meta_path, "javascript_include", include));
+                }
+            }
+            for include in menu_meta
+                .section_css_include
+                .iter()
+                .chain(menu_meta.css_include.iter())
+            {
+                if !asset_exists(include) {
+                    broken.push(broken_link(&meta_path, "css_include", include));
+                }
+            }
+        }
+        broken.append(&mut check_menu_metas(sub_tree));
+    }
+
+    broken
+}
+
+fn broken_link(file: &Path, kind: &str, target: &str) -> BrokenLink {
+    BrokenLink {
+        file: file.to_string_lossy().to_string(),
+        line: find_line(file, target),
+        kind: kind.to_string(),
+        target: target.to_string(),
+    }
+}
+
+/// Line number of the first occurrence of needle in a file, 0 if the file can't be read or it isn't there
+fn find_line(file: &Path, needle: &str) -> usize {
+    match fs::read_to_string(file) {
+        Ok(content) => line_of(&content, needle),
+        Err(_) => 0,
+    }
+}
+
+/// 1 based line number of the first line containing needle, 0 if there isn't one
+pub fn line_of(content: &str, needle: &str) -> usize {
+    for (index, line) in content.lines().enumerate() {
+        if line.contains(needle) {
+            return index + 1;
+        }
+    }
+    0
+}
+
+/// Pull every href and src attribute value out of a chunk of rendered HTML
+pub(crate) fn extract_links(html: &str) -> Vec<String> {
+    let mut links = extract_attribute_values(html, "href");
+    links.append(&mut extract_attribute_values(html, "src"));
+    links
+}
+
+/// Naive attribute scanner, good enough for the HTML comrak renders and hand written content
+pub(crate) fn extract_attribute_values(html: &str, attribute: &str) -> Vec<String> {
+    let mut values: Vec<String> = Vec::new();
+    let lowered = html.to_ascii_lowercase();
+    let pattern = format!("{}=", attribute);
+    let mut start = 0;
+
+    while let Some(found) = lowered[start..].find(&pattern) {
+        let attr_start = start + found;
+        let value_start = attr_start + pattern.len();
+        start = value_start;
+        // Only match whole attribute names, "data-src=" is not "src="
+        let preceding = lowered[..attr_start].chars().last().unwrap_or(' ');
+        if !preceding.is_whitespace() {
+            continue;
+        }
+        let rest = &html[value_start..];
+        let value = match rest.chars().next() {
+            Some(quote) if quote == '"' || quote == '\'' => match rest[1..].find(quote) {
+                Some(end) => &rest[1..end + 1],
+                None => continue,
+            },
+            Some(_) => rest
+                .split(|c: char| c.is_whitespace() || c == '>')
+                .next()
+                .unwrap_or(""),
+            None => continue,
+        };
+        values.push(unescape_attribute(value));
+    }
+
+    values
+}
+
+fn unescape_attribute(value: &str) -> String {
+    value
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&amp;", "&")
+}
+
+/// Turn a link found on a page into an absolute web path, None for external or in page links
+///
+/// Parameters:
+///     page_webpath(&str), web path of the page the link was found on
+///     target(&str), the raw href or src value
+/// Returns:
+///     Option<String>, absolute web path without query, fragment or trailing slash
+pub(crate) fn normalize_link(page_webpath: &str, target: &str) -> Option<String> {
+    let target = target.trim();
+    if target.is_empty() || target.starts_with("#") || target.starts_with("//") {
+        return None;
+    }
+    // Anything with a scheme (https:, mailto:, data: ...) is not ours to check
+    if let Some(colon) = target.find(":") {
+        if !target[..colon].contains("/") {
+            return None;
+        }
+    }
+    let without_fragment = target.split(['#', '?']).next().unwrap_or("");
+
+    let joined = if without_fragment.starts_with("/") {
+        without_fragment.to_string()
+    } else {
+        let page_dir = match page_webpath.rfind("/") {
+            Some(index) => &page_webpath[..index + 1],
+            None => "/",
+        };
+        format!("{}{}", page_dir, without_fragment)
+    };
+
+    let mut segments: Vec<&str> = Vec::new();
+    for segment in joined.split("/") {
+        match segment {
+            "" | "." => {}
+            ".." => {
+                segments.pop();
+            }
+            _ => segments.push(segment),
+        }
+    }
+
+    Some(format!("/{}", segments.join("/")))
+}
+
+/// Does a link found on a page point at existing content, a directory or a static file
+pub fn link_resolves(page_webpath: &str, target: &str) -> bool {
+    match normalize_link(page_webpath, target) {
+        Some(webpath) => webpath_resolves(&webpath),
+        None => true,
+    }
+}
+
+/// Does an icon or include path point at a file that exists
+pub fn asset_exists(asset_path: &str) -> bool {
+    match normalize_link("/", asset_path) {
+        Some(webpath) => webpath_resolves(&webpath),
+        None => true,
+    }
+}
+
+fn webpath_resolves(webpath: &str) -> bool {
+    if webpath == "/" {
+        return true;
+    }
+    if webpath.starts_with("/static/") {
+        // Pages point at the fingerprinted copies once assets are built
+        return static_webpath_to_localpath(webpath).is_file() || is_built_asset(webpath);
+    }
+    let route = route_webpath(webpath, &site_languages());
+    route == "/"
+        || does_content_exist(route.clone())
+        || does_directory_exist(route.clone())
+        || Path::new(&webpath_to_localpath(route.clone())).is_file()
+        || (is_under_taxonomy(&route) && is_term_listing(&route, &generate_taxonomy_index()))
+}
+
+/// The web path of what serves a route, language versions are served by the default language page and later
+/// pages of a listing by the listing
+///
+/// Parameters:
+///     webpath(&str), a normalized link such as "/es/blog/page/2"
+///     languages(&[String]), from site_languages()
+/// Returns:
+///     String, the web path to look for, "/blog"
+fn route_webpath(webpath: &str, languages: &[String]) -> String {
+    let (_, unprefixed) = split_language_prefix_in(webpath, languages);
+    let (listing, _) = split_page_path(&unprefixed);
+    listing
+}
+
+/// Is a web path under /tags/ or /categories/, only those are worth building the taxonomy index for
+fn is_under_taxonomy(webpath: &str) -> bool {
+    let first = webpath
+        .trim_start_matches('/')
+        .split('/')
+        .next()
+        .unwrap_or("");
+    first == TAXONOMY_TAGS || first == TAXONOMY_CATEGORIES
+}
+
+/// Is a web path the listing page of a term in the index
+fn is_term_listing(webpath: &str, index: &TaxonomyIndex) -> bool {
+    index.term_webpaths().iter().any(|x| x == webpath)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn extracts_href_and_src() {
+        let html =
+            r#"<p><a href="/blog/post">post</a> <img data-src="/x.png" src='/static/a.png'></p>"#;
+        assert_eq!(extract_links(html), vec!["/blog/post", "/static/a.png"]);
+    }
+
+    #[test]
+    fn normalizes_relative_and_external_links() {
+        assert_eq!(
+            normalize_link("/blog/post", "../about/#team"),
+            Some(String::from("/about"))
+        );
+        assert_eq!(
+            normalize_link("/blog/post", "other?page=2"),
+            Some(String::from("/blog/other"))
+        );
+        assert_eq!(normalize_link("/blog/post", "https://example.com/"), None);
+        assert_eq!(normalize_link("/blog/post", "#top"), None);
+    }
+
+    #[test]
+    fn routes_listing_pages_to_their_listing() {
+        assert_eq!(route_webpath("/blog/page/2", &[]), "/blog");
+        assert_eq!(route_webpath("/page/3", &[]), "/");
+        assert_eq!(route_webpath("/tags/rust/page/2", &[]), "/tags/rust");
+        assert_eq!(route_webpath("/blog/page/x", &[]), "/blog/page/x");
+    }
+
+    #[test]
+    fn routes_language_versions_to_their_page() {
+        let languages = vec![String::from("en"), String::from("es")];
+        assert_eq!(route_webpath("/es/blog/first", &languages), "/blog/first");
+        assert_eq!(route_webpath("/es", &languages), "/");
+        assert_eq!(route_webpath("/es/blog/page/2", &languages), "/blog");
+        assert_eq!(route_webpath("/en/blog", &languages), "/en/blog"); // The default has no prefix
+        assert_eq!(route_webpath("/es/blog", &[]), "/es/blog");
+    }
+
+    #[test]
+    fn resolves_term_listings() {
+        let mut index = TaxonomyIndex::default();
+        let meta = crate::ContentMeta {
+            tags: vec![String::from("Rust")],
+            ..crate::ContentMeta::default()
+        };
+        let page = crate::taxonomy::TermPage {
+            path: String::from("/blog/post"),
+            title: String::new(),
+            description: String::new(),
+            weight: 0,
+            created: chrono::Utc::now(),
+            modified: chrono::Utc::now(),
+        };
+        index.insert(&meta, page);
+        assert!(is_under_taxonomy("/tags/rust") && is_term_listing("/tags/rust", &index));
+        assert!(!is_term_listing("/tags/go", &index));
+        assert!(!is_term_listing("/categories/rust", &index));
+        assert!(!is_under_taxonomy("/blog/tags"));
+    }
+
+    #[test]
+    fn finds_line_numbers() {
+        assert_eq!(line_of("one\ntwo [x](/two)\nthree", "/two"), 2);
+        assert_eq!(line_of("one", "/missing"), 0);
+    }
+}
@@ -0,0 +1,186 @@
+//! Dynamic content_list entries
+//!
+//! Besides literal web paths a ContentMeta.content_list entry can be
+//!
+//! a glob
+//!     "/blog/*" matches the pages directly in /blog, "/docs/**" everything below /docs, "?" a single character
+//! a query
+//!     "query type=post sort=created order=descending limit=5", see query::parse_query_expression()
+//!
+//! Entries are expanded when the page is loaded, in list order with duplicates dropped.  A list of web paths
+//! and globs is sorted by weight like before, a list with a query keeps the expanded order so "latest"
+//! queries stay in date order.  The page itself and pages already being loaded further up are skipped so
+//! lists can't recurse forever.
+use std::cell::RefCell;
+
+use crate::publication::section_is_visible;
+use crate::query::{parse_query_expression, query_webpaths, ContentQuery};
+use crate::{generate_content_state, tree_to_webpaths};
+
+/// One parsed content_list entry
+#[derive(Debug, Clone, PartialEq)]
+pub enum ContentListEntry {
+    Path(String),
+    Glob(String),
+    Query(Box<ContentQuery>),
+}
+
+/// Work out what kind of entry a content_list string is
+///
+/// Returns:
+///     Result<ContentListEntry, String>, Err for a query expression that doesn't parse
+pub fn parse_entry(entry: &str) -> Result<ContentListEntry, String> {
+    let entry = entry.trim();
+    if entry == "query" || entry.starts_with("query ") {
+        return parse_query_expression(&entry["query".len()..])
+            .map(|x| ContentListEntry::Query(Box::new(x)));
+    }
+    if entry.contains('*') || entry.contains('?') {
+        return Ok(ContentListEntry::Glob(entry.to_string()));
+    }
+    Ok(ContentListEntry::Path(entry.to_string()))
+}
+
+/// Expand a content_list into web paths
+///
+/// Parameters:
+///     list_o_content(&[String]), the content_list entries
+/// Returns:
+///     (Vec<String>, bool), the web paths in expanded order and whether that order should be kept (a query
+///     was used) instead of sorting by weight
+pub fn expand_content_list(list_o_content: &[String]) -> (Vec<String>, bool) {
+    let mut webpaths: Vec<String> = Vec::new();
+    let mut keep_order = false;
+    let mut site_webpaths: Option<Vec<String>> = None; // Only walk the tree if a glob needs it
+
+    for item in list_o_content {
+        let expanded = match parse_entry(item) {
+            Ok(ContentListEntry::Path(webpath)) => vec![webpath],
+            Ok(ContentListEntry::Glob(pattern)) => site_webpaths
+                .get_or_insert_with(|| tree_to_webpaths(&generate_content_state()))
+                .iter()
+                .filter(|x| glob_matches(&pattern, x) && section_is_visible(x))
+                .cloned()
+                .collect(),
+            Ok(ContentListEntry::Query(query)) => {
+                keep_order = true;
+                query_webpaths(&query)
+            }
+            Err(why) => {
+                println!("Content list failure.  Bad query {}: {}", item, why); // TODO Change to logging
+                Vec::new()
+            }
+        };
+        for webpath in expanded {
+            if is_loading(&webpath) {
+                continue; // The page itself or one of the pages listing it
+            }
+            if !webpaths.contains(&webpath) {
+                webpaths.push(webpath);
+            }
+        }
+    }
+    (webpaths, keep_order)
+}
+
+/// Does a web path match a glob, "*" and "?" stay within a path segment and a "**" segment spans any number
+/// of segments
+pub fn glob_matches(pattern: &str, webpath: &str) -> bool {
+    let pattern: Vec<&str> = pattern.trim_matches('/').split('/').collect();
+    let webpath: Vec<&str> = webpath.trim_matches('/').split('/').collect();
+    segments_match(&pattern, &webpath)
+}
+
+fn segments_match(pattern: &[&str], webpath: &[&str]) -> bool {
+    match pattern.first() {
+        None => webpath.is_empty(),
+        Some(&"**") => {
+            (0..=webpath.len()).any(|skip| segments_match(&pattern[1..], &webpath[skip..]))
+        }
+        Some(segment) => match webpath.first() {
+            Some(name) => {
+                let segment: Vec<char> = segment.chars().collect();
+                let name: Vec<char> = name.chars().collect();
+                segment_matches(&segment, &name) && segments_match(&pattern[1..], &webpath[1..])
+            }
+            None => false,
+        },
+    }
+}
+
+fn segment_matches(pattern: &[char], name: &[char]) -> bool {
+    match pattern.first() {
+        None => name.is_empty(),
+        Some('*') => (0..=name.len()).any(|skip| segment_matches(&pattern[1..], &name[skip..])),
+        Some('?') => !name.is_empty() && segment_matches(&pattern[1..], &name[1..]),
+        Some(c) => name.first() == Some(c) && segment_matches(&pattern[1..], &name[1..]),
+    }
+}
+
+thread_local! {
+    // Web paths of the pages read_single_page() is in the middle of loading on this thread
+    static LOADING: RefCell<Vec<String>> = RefCell::default();
+}
+
+/// Marks a page as being loaded until the guard is dropped
+pub struct LoadingGuard;
+
+impl Drop for LoadingGuard {
+    fn drop(&mut self) {
+        LOADING.with(|x| x.borrow_mut().pop());
+    }
+}
+
+/// Record that a page is being loaded, hold on to the guard for as long as it is
+pub fn enter_page(webpath: &str) -> LoadingGuard {
+    LOADING.with(|x| x.borrow_mut().push(webpath.to_string()));
+    LoadingGuard
+}
+
+/// Is the page being loaded further up the call stack
+pub fn is_loading(webpath: &str) -> bool {
+    LOADING.with(|x| x.borrow().iter().any(|loading| loading == webpath))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_entry_kinds() {
+        assert_eq!(
+            parse_entry("/blog/first").unwrap(),
+            ContentListEntry::Path(String::from("/blog/first"))
+        );
+        assert_eq!(
+            parse_entry("/blog/*").unwrap(),
+            ContentListEntry::Glob(String::from("/blog/*"))
+        );
+        match parse_entry("query type=post limit=5").unwrap() {
+            ContentListEntry::Query(query) => assert_eq!(query.limit, Some(5)),
+            other => panic!("Expected a query, got {:?}", other),
+        }
+        assert!(parse_entry("query limit=many").is_err());
+    }
+
+    #[test]
+    fn matches_globs() {
+        assert!(glob_matches("/blog/*", "/blog/first"));
+        assert!(!glob_matches("/blog/*", "/blog/2021/first"));
+        assert!(glob_matches("/blog/**", "/blog/2021/first"));
+        assert!(glob_matches("/**/first", "/first"));
+        assert!(glob_matches("/blog/post-?", "/blog/post-1"));
+        assert!(!glob_matches("/blog/post-?", "/blog/post-10"));
+    }
+
+    #[test]
+    fn skips_pages_being_loaded() {
+        let _guard = enter_page("/index");
+        let (webpaths, keep_order) =
+            expand_content_list(&[String::from("/index"), String::from("/about")]);
+        assert_eq!(webpaths, vec!["/about"]);
+        assert!(!keep_order);
+        drop(_guard);
+        assert!(!is_loading("/index"));
+    }
+}
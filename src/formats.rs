@@ -0,0 +1,455 @@
+//! Content format registry
+//!
+//! Every content file extension n4 knows about comes from here.  does_content_exist(), directory listings, the
+//! sitemap and the web path walk all ask the registry, so a new format only has to be registered once.
+//!
+//! md, html and json keep their own fields on PageContent.  Every other format that has a file for a page is
+//! rendered into PageContent.formatted, in registry order.  Built in formats besides the core three are
+//! rst (reStructuredText flavored plain text), adoc (an AsciiDoc subset) and ipynb (Jupyter notebooks), see
+//! the markup module, and csv and tsv tables, see the table_content module.  Plain .txt files aren't content
+//! so a LICENSE.txt next to the pages stays a file.
+//!
+//! register_format() adds a format to the process wide FormatRegistry, a format with the extension of a built
+//! in one replaces it.  Replacing md, html or json moves that extension out of its PageContent field and into
+//! PageContent.formatted, rendered by the replacement.
+use std::fs;
+use std::path::Path;
+use std::sync::{Arc, RwLock};
+
+use serde_derive::{Deserialize, Serialize};
+use v_htmlescape::escape;
+
+use crate::json_content::{parse_json_content, render_json_content};
+use crate::markup::{render_asciidoc, render_notebook, render_plain_text};
+use crate::table_content::{render_table_content, table_delimiter};
+use crate::{
+    content_path_with_extension, read_file_creation_time, read_file_modified_time, render_markdown,
+    unix_time_to_iso, ContentMeta, MenuItemMeta,
+};
+
+/// Extensions with their own PageContent fields, read by read_single_page() directly unless replaced
+pub const CORE_EXTENSIONS: [&str; 3] = ["md", "html", "json"];
+
+/// A content file format
+pub trait ContentFormat: Send + Sync {
+    /// Short human readable name such as "AsciiDoc"
+    fn name(&self) -> &str;
+
+    /// File extension without the dot, unique in the registry
+    fn extension(&self) -> &str;
+
+    /// Render the source of a content file to HTML, the result still goes through the section sanitize policy
+    ///
+    /// Parameters:
+    ///     source(&str), the file contents
+    ///     meta(&ContentMeta), meta of the page being rendered
+    ///     section_meta(&MenuItemMeta), meta of the section the page is in
+    /// Returns:
+    ///     Result<String, String>, the HTML or why the source couldn't be rendered
+    fn render(
+        &self,
+        source: &str,
+        meta: &ContentMeta,
+        section_meta: &MenuItemMeta,
+    ) -> Result<String, String>;
+}
+
+/// The core formats render the same way read_single_page() fills their PageContent fields, for callers going
+/// through format_for_extension()
+pub struct Markdown;
+
+impl ContentFormat for Markdown {
+    fn name(&self) -> &str {
+        "Markdown"
+    }
+    fn extension(&self) -> &str {
+        "md"
+    }
+    fn render(
+        &self,
+        source: &str,
+        _: &ContentMeta,
+        section: &MenuItemMeta,
+    ) -> Result<String, String> {
+        Ok(render_markdown(source, section.sanitize.markdown_raw_html))
+    }
+}
+
+pub struct Html;
+
+impl ContentFormat for Html {
+    fn name(&self) -> &str {
+        "HTML"
+    }
+    fn extension(&self) -> &str {
+        "html"
+    }
+    fn render(&self, source: &str, _: &ContentMeta, _: &MenuItemMeta) -> Result<String, String> {
+        Ok(source.to_string())
+    }
+}
+
+pub struct Json;
+
+impl ContentFormat for Json {
+    fn name(&self) -> &str {
+        "JSON"
+    }
+    fn extension(&self) -> &str {
+        "json"
+    }
+    fn render(&self, source: &str, meta: &ContentMeta, _: &MenuItemMeta) -> Result<String, String> {
+        let (body, errors) = parse_json_content(source, &meta.content_type);
+        if !errors.is_empty() {
+            return Err(errors.join("; "));
+        }
+        match render_json_content(&body, &meta.content_type) {
+            Some(rendered) => Ok(rendered),
+            None => Ok(format!(
+                "<pre>{}</pre>",
+                escape(&serde_json::to_string_pretty(&body).unwrap_or_default())
+            )),
+        }
+    }
+}
+
+pub struct PlainText;
+
+impl ContentFormat for PlainText {
+    fn name(&self) -> &str {
+        "Plain text"
+    }
+    fn extension(&self) -> &str {
+        "rst"
+    }
+    fn render(&self, source: &str, _: &ContentMeta, _: &MenuItemMeta) -> Result<String, String> {
+        Ok(render_plain_text(source))
+    }
+}
+
+pub struct AsciiDoc;
+
+impl ContentFormat for AsciiDoc {
+    fn name(&self) -> &str {
+        "AsciiDoc"
+    }
+    fn extension(&self) -> &str {
+        "adoc"
+    }
+    fn render(&self, source: &str, _: &ContentMeta, _: &MenuItemMeta) -> Result<String, String> {
+        Ok(render_asciidoc(source))
+    }
+}
+
+pub struct Notebook;
+
+impl ContentFormat for Notebook {
+    fn name(&self) -> &str {
+        "Jupyter notebook"
+    }
+    fn extension(&self) -> &str {
+        "ipynb"
+    }
+    fn render(
+        &self,
+        source: &str,
+        _: &ContentMeta,
+        section: &MenuItemMeta,
+    ) -> Result<String, String> {
+        render_notebook(source, section.sanitize.markdown_raw_html)
+    }
+}
+
+pub struct Csv;
+
+impl ContentFormat for Csv {
+    fn name(&self) -> &str {
+        "CSV"
+    }
+    fn extension(&self) -> &str {
+        "csv"
+    }
+    fn render(&self, source: &str, meta: &ContentMeta, _: &MenuItemMeta) -> Result<String, String> {
+        render_table_content(source, table_delimiter("csv", &meta.table), &meta.table)
+    }
+}
+
+pub struct Tsv;
+
+impl ContentFormat for Tsv {
+    fn name(&self) -> &str {
+        "TSV"
+    }
+    fn extension(&self) -> &str {
+        "tsv"
+    }
+    fn render(&self, source: &str, meta: &ContentMeta, _: &MenuItemMeta) -> Result<String, String> {
+        render_table_content(source, table_delimiter("tsv", &meta.table), &meta.table)
+    }
+}
+
+/// The formats n4 knows about, in lookup order
+///
+/// The default holds the built in formats.  register_format() and the lookups below use the process wide
+/// registry, a FormatRegistry value of its own leaves that alone.
+#[derive(Clone)]
+pub struct FormatRegistry {
+    formats: Vec<Arc<dyn ContentFormat>>,
+    replaced: Vec<String>, // Extensions a registered format took over from a built in one
+}
+
+impl Default for FormatRegistry {
+    fn default() -> Self {
+        FormatRegistry {
+            formats: vec![
+                Arc::new(Markdown),
+                Arc::new(Html),
+                Arc::new(Json),
+                Arc::new(PlainText),
+                Arc::new(AsciiDoc),
+                Arc::new(Notebook),
+                Arc::new(Csv),
+                Arc::new(Tsv),
+            ],
+            replaced: Vec::new(),
+        }
+    }
+}
+
+impl FormatRegistry {
+    /// Add a format, a format with the same extension is replaced in place
+    pub fn register(&mut self, format: Arc<dyn ContentFormat>) {
+        let extension = format.extension().to_string();
+        match self.formats.iter().position(|x| x.extension() == extension) {
+            Some(index) => {
+                self.formats[index] = format;
+                if !self.replaced.contains(&extension) {
+                    self.replaced.push(extension);
+                }
+            }
+            None => self.formats.push(format),
+        }
+    }
+
+    pub fn formats(&self) -> &[Arc<dyn ContentFormat>] {
+        &self.formats
+    }
+
+    pub fn format_for_extension(&self, extension: &str) -> Option<Arc<dyn ContentFormat>> {
+        self.formats
+            .iter()
+            .find(|x| x.extension() == extension)
+            .cloned()
+    }
+
+    /// Is an extension read into its own PageContent field by read_single_page(), true for md, html and json
+    /// unless a registered format replaced them
+    pub fn uses_core_reader(&self, extension: &str) -> bool {
+        CORE_EXTENSIONS.contains(&extension) && !self.replaced.iter().any(|x| x == extension)
+    }
+}
+
+// The process wide registry, None until a format is registered
+static REGISTRY: RwLock<Option<FormatRegistry>> = RwLock::new(None);
+
+fn registry() -> FormatRegistry {
+    match REGISTRY.read() {
+        Err(why) => panic!("Format registry lock poisoned: {}", why),
+        Ok(val) => val.clone().unwrap_or_default(),
+    }
+}
+
+/// Add a format to the process wide registry, replacing any format with the same extension
+pub fn register_format(format: Box<dyn ContentFormat>) {
+    let mut registry = match REGISTRY.write() {
+        Err(why) => panic!("Format registry lock poisoned: {}", why),
+        Ok(val) => val,
+    };
+    registry
+        .get_or_insert_with(FormatRegistry::default)
+        .register(Arc::from(format));
+}
+
+/// Every format in lookup order, built in formats first with registered ones replacing or following them
+pub fn formats() -> Vec<Arc<dyn ContentFormat>> {
+    registry().formats
+}
+
+/// Is an extension read into its own PageContent field by read_single_page(), see
+/// FormatRegistry::uses_core_reader()
+pub fn uses_core_reader(extension: &str) -> bool {
+    registry().uses_core_reader(extension)
+}
+
+/// The format for a file extension, without the dot
+pub fn format_for_extension(extension: &str) -> Option<Arc<dyn ContentFormat>> {
+    registry().format_for_extension(extension)
+}
+
+/// Every content file extension in lookup order
+pub fn content_extensions() -> Vec<String> {
+    formats()
+        .iter()
+        .map(|x| x.extension().to_string())
+        .collect()
+}
+
+/// Is this extension (without the dot) a content file
+pub fn is_content_extension(extension: &str) -> bool {
+    format_for_extension(extension).is_some()
+}
+
+/// Content in one of the non core formats, rendered to HTML
+///
+/// body
+///     Rendered HTML, empty if rendering failed
+/// errors
+///     Why the file couldn't be rendered
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FormattedContent {
+    pub format: String,
+    pub extension: String,
+    pub created: chrono::DateTime<chrono::Utc>,
+    pub modified: chrono::DateTime<chrono::Utc>,
+    pub body: String,
+    pub errors: Vec<String>,
+}
+
+impl Default for FormattedContent {
+    fn default() -> Self {
+        FormattedContent {
+            format: String::from(""),
+            extension: String::from(""),
+            created: unix_time_to_iso(0.0),
+            modified: unix_time_to_iso(0.0),
+            body: String::from(""),
+            errors: Vec::new(),
+        }
+    }
+}
+
+/// Render every file that exists for a page in a format without its own PageContent field
+///
+/// Parameters:
+///     full_path_string(&str), absolute path of the page without an extension
+///     meta(&ContentMeta), the page meta
+///     section_meta(&MenuItemMeta), meta of the section the page is in
+/// Returns:
+///     Vec<FormattedContent>, in registry order
+pub fn read_formatted_content(
+    full_path_string: &str,
+    meta: &ContentMeta,
+    section_meta: &MenuItemMeta,
+) -> Vec<FormattedContent> {
+    let mut formatted: Vec<FormattedContent> = Vec::new();
+    for format in formats() {
+        if uses_core_reader(format.extension()) {
+            continue;
+        }
+        let this_path = content_path_with_extension(full_path_string, format.extension());
+        if !this_path.exists() {
+            continue;
+        }
+        let (source, mut errors) = read_format_source(&this_path);
+        let body = match format.render(&source, meta, section_meta) {
+            Ok(val) => val,
+            Err(why) => {
+                errors.push(why);
+                String::new()
+            }
+        };
+        formatted.push(FormattedContent {
+            format: format.name().to_string(),
+            extension: format.extension().to_string(),
+            created: read_file_creation_time(&this_path),
+            modified: read_file_modified_time(&this_path),
+            body,
+            errors,
+        });
+    }
+    formatted
+}
+
+/// Read the source of a content file, bytes that aren't UTF-8 (a Latin-1 spreadsheet export) are replaced
+///
+/// Returns:
+///     (String, Vec<String>), the source and an error saying so if anything was replaced
+fn read_format_source(path: &Path) -> (String, Vec<String>) {
+    let bytes = match fs::read(path) {
+        Err(why) => panic!("Couldn't read {}: {}", path.to_string_lossy(), why),
+        Ok(val) => val,
+    };
+    match String::from_utf8(bytes) {
+        Ok(val) => (val, Vec::new()),
+        Err(why) => (
+            String::from_utf8_lossy(why.as_bytes()).to_string(),
+            vec![format!(
+                "{} isn't UTF-8, characters that couldn't be read were replaced",
+                path.to_string_lossy()
+            )],
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Shout;
+
+    impl ContentFormat for Shout {
+        fn name(&self) -> &str {
+            "Shouting"
+        }
+        fn extension(&self) -> &str {
+            "ipynb"
+        }
+        fn render(
+            &self,
+            source: &str,
+            _: &ContentMeta,
+            _: &MenuItemMeta,
+        ) -> Result<String, String> {
+            Ok(source.to_uppercase())
+        }
+    }
+
+    #[test]
+    fn reads_sources_that_arent_utf8() {
+        let path = std::env::temp_dir().join(format!("n4-latin1-{}.csv", std::process::id()));
+        fs::write(&path, b"name\ncaf\xe9\n").unwrap();
+        let (source, errors) = read_format_source(&path);
+        assert_eq!(source, "name\ncaf\u{fffd}\n");
+        assert_eq!(errors.len(), 1);
+        fs::write(&path, "name\ncaf\u{e9}\n").unwrap();
+        assert_eq!(
+            read_format_source(&path),
+            (String::from("name\ncaf\u{e9}\n"), Vec::new())
+        );
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn registered_formats_replace_builtins_in_place() {
+        assert!(is_content_extension("adoc"));
+        assert!(!is_content_extension("content_meta"));
+        assert!(!is_content_extension("txt"));
+        let mut registry = FormatRegistry::default();
+        registry.register(Arc::new(Shout));
+        let extensions: Vec<&str> = registry.formats().iter().map(|x| x.extension()).collect();
+        assert_eq!(extensions[5], "ipynb");
+        assert_eq!(extensions.iter().filter(|x| **x == "ipynb").count(), 1);
+        let format = registry.format_for_extension("ipynb").unwrap();
+        assert_eq!(format.name(), "Shouting");
+        let rendered = format
+            .render("hi", &ContentMeta::default(), &MenuItemMeta::default())
+            .unwrap();
+        assert_eq!(rendered, "HI");
+        assert!(registry.uses_core_reader("md") && !registry.uses_core_reader("ipynb"));
+        // Only the local registry changed
+        assert_eq!(
+            format_for_extension("ipynb").unwrap().name(),
+            "Jupyter notebook"
+        );
+    }
+}
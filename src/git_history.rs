@@ -0,0 +1,138 @@
+//! Git backed created and modified dates
+//!
+//! File system times are wrong after a git clone (everything was created at clone time) so when the
+//! git_history config value is on the first and last commit touching a content file are used instead.
+//! file_history() is the entry point for pages, sitemaps and feeds, it falls back from git to the file system
+//! and then to the unix epoch.
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::Mutex;
+
+use chrono::prelude::*;
+use serde_derive::{Deserialize, Serialize};
+
+use crate::{load_config, read_file_creation_time, read_file_modified_time, unix_time_to_iso};
+
+/// Created and modified dates of a file along with who made those changes when known
+///
+/// source
+///     "git", "filesystem" or "none" if the file doesn't exist
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct FileHistory {
+    pub created: DateTime<Utc>,
+    pub created_author: String,
+    pub modified: DateTime<Utc>,
+    pub modified_author: String,
+    pub source: String,
+}
+
+impl Default for FileHistory {
+    fn default() -> Self {
+        FileHistory {
+            created: unix_time_to_iso(0.0),
+            created_author: String::from(""),
+            modified: unix_time_to_iso(0.0),
+            modified_author: String::from(""),
+            source: String::from("none"),
+        }
+    }
+}
+
+// Running git once per file is slow enough to be worth remembering for the life of the process
+static GIT_CACHE: Mutex<Option<HashMap<PathBuf, Option<FileHistory>>>> = Mutex::new(None);
+
+/// Dates for a content file using the configured fallback chain: git (if enabled), file system, epoch
+pub fn file_history(path: &Path) -> FileHistory {
+    if load_config().git_history {
+        if let Some(history) = git_file_history(path) {
+            return history;
+        }
+    }
+    if path.exists() {
+        return FileHistory {
+            created: read_file_creation_time(path),
+            modified: read_file_modified_time(path),
+            source: String::from("filesystem"),
+            ..FileHistory::default()
+        };
+    }
+    FileHistory::default()
+}
+
+/// First and last commit of a file according to git log, following renames
+///
+/// Returns:
+///     Option<FileHistory>, None if git isn't available, the file isn't in a repository or isn't committed
+pub fn git_file_history(path: &Path) -> Option<FileHistory> {
+    let key = PathBuf::from(path);
+    if let Ok(cache) = GIT_CACHE.lock() {
+        if let Some(cached) = cache.as_ref().and_then(|x| x.get(&key)) {
+            return cached.clone();
+        }
+    }
+
+    let history = read_git_log(path);
+
+    if let Ok(mut cache) = GIT_CACHE.lock() {
+        cache
+            .get_or_insert_with(HashMap::new)
+            .insert(key, history.clone());
+    }
+    history
+}
+
+fn read_git_log(path: &Path) -> Option<FileHistory> {
+    let dir = path.parent()?;
+    let file_name = path.file_name()?;
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(["log", "--follow", "--format=%aI%x09%an", "--"])
+        .arg(file_name)
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    parse_git_log(&String::from_utf8_lossy(&output.stdout))
+}
+
+/// Parse `git log --format=%aI%x09%an` output, newest commit first
+pub fn parse_git_log(log: &str) -> Option<FileHistory> {
+    let commits: Vec<(DateTime<Utc>, String)> = log
+        .lines()
+        .filter_map(|line| {
+            let mut parts = line.splitn(2, '\t');
+            let date = DateTime::parse_from_rfc3339(parts.next()?.trim()).ok()?;
+            let author = parts.next().unwrap_or("").trim().to_string();
+            Some((date.with_timezone(&Utc), author))
+        })
+        .collect();
+    let (modified, modified_author) = commits.first()?.clone();
+    let (created, created_author) = commits.last()?.clone();
+    Some(FileHistory {
+        created,
+        created_author,
+        modified,
+        modified_author,
+        source: String::from("git"),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_first_and_last_commit() {
+        let log = "2021-03-02T09:00:00+01:00\tSecond Author\n\
+                   2021-02-01T12:30:00Z\tFirst Author\n";
+        let history = parse_git_log(log).unwrap();
+        assert_eq!(history.created.to_rfc3339(), "2021-02-01T12:30:00+00:00");
+        assert_eq!(history.created_author, "First Author");
+        assert_eq!(history.modified.to_rfc3339(), "2021-03-02T08:00:00+00:00");
+        assert_eq!(history.modified_author, "Second Author");
+        assert!(parse_git_log("").is_none());
+    }
+}
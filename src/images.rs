@@ -0,0 +1,389 @@
+//! Responsive images
+//!
+//! With `images.enabled` in the config, build_static_assets() also makes resized copies of every png, jpeg
+//! and gif under the static directory at each of the configured widths narrower than the original, plus a
+//! thumbnail cropped to fill thumbnail_width by thumbnail_height.  Copies are named from the source hash and
+//! their size, "/static/images/a.png" at 480 pixels wide becomes "/static/images/a.1f2e3d4c5b6a7988.480w.png",
+//! so a copy that is already in output_dir is up to date and isn't made again.
+//!
+//! The manifest records the copies of each image, rewrite_page_assets() then gives the <img> tags of rendered
+//! bodies a srcset, sizes and the width and height of the original, and a content_icon that is a processed
+//! image is swapped for its thumbnail.  Animated gifs only keep their first frame in the copies.
+use std::path::{Path, PathBuf};
+
+use image::imageops::FilterType;
+use image::DynamicImage;
+use serde_derive::{Deserialize, Serialize};
+
+use crate::assets::{insert_before_extension, AssetManifest, HASH_LENGTH};
+
+/// Extensions of the images that get resized copies
+pub const IMAGE_EXTENSIONS: [&str; 4] = ["png", "jpg", "jpeg", "gif"];
+
+/// Image settings of the site config
+///
+/// enabled
+///     Make resized copies and thumbnails when building static assets
+/// widths
+///     Widths in pixels of the resized copies, only those narrower than the original are made
+/// thumbnail_width, thumbnail_height
+///     Size in pixels of the thumbnail, the image is scaled and cropped to fill it
+/// sizes
+///     sizes attribute added with the srcset when an <img> doesn't have one
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct ImageOptions {
+    pub enabled: bool,
+    pub widths: Vec<u32>,
+    pub thumbnail_width: u32,
+    pub thumbnail_height: u32,
+    pub sizes: String,
+}
+
+impl Default for ImageOptions {
+    fn default() -> Self {
+        ImageOptions {
+            enabled: false,
+            widths: vec![480, 960, 1440],
+            thumbnail_width: 240,
+            thumbnail_height: 240,
+            sizes: String::from("100vw"),
+        }
+    }
+}
+
+/// A resized copy of an image
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ImageVariant {
+    pub path: String,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// The processed copies of one image
+///
+/// width, height
+///     Size of the original
+/// variants
+///     Resized copies, narrowest first
+/// thumbnail
+///     Web path of the thumbnail
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ImageEntry {
+    pub width: u32,
+    pub height: u32,
+    pub variants: Vec<ImageVariant>,
+    pub thumbnail: String,
+}
+
+/// Does a static file get resized copies
+pub fn is_processable(webpath: &str) -> bool {
+    match webpath.rsplit('.').next() {
+        Some(extension) => IMAGE_EXTENSIONS.contains(&extension.to_lowercase().as_str()),
+        None => false,
+    }
+}
+
+/// Make the resized copies of every processable image in the manifest and record them
+///
+/// Parameters:
+///     manifest(&mut AssetManifest), the fingerprinted static assets, their hashes key the copies
+///     output_dir(&str), the build output directory
+///     options(&ImageOptions), from the site config
+pub fn build_images(manifest: &mut AssetManifest, output_dir: &str, options: &ImageOptions) {
+    let images: Vec<(String, String)> = manifest
+        .assets
+        .iter()
+        .filter(|(webpath, _)| is_processable(webpath))
+        .map(|(webpath, entry)| (webpath.clone(), entry.sha256.clone()))
+        .collect();
+    for (webpath, sha256) in images {
+        let source_path =
+            PathBuf::from(format!("{}{}", output_dir, manifest.assets[&webpath].path));
+        match process_image(&source_path, &webpath, &sha256, output_dir, options) {
+            Err(why) => println!("Skipping image {}: {}", webpath, why), // TODO Change to logging
+            Ok(entry) => {
+                manifest.images.insert(webpath, entry);
+            }
+        }
+    }
+}
+
+/// Make the resized copies and thumbnail of one image, copies that already exist are kept
+///
+/// Parameters:
+///     source_path(&Path), the image file
+///     webpath(&str), original web path of the image
+///     sha256(&str), hex digest of the image file
+///     output_dir(&str), the build output directory
+///     options(&ImageOptions), from the site config
+/// Returns:
+///     Result<ImageEntry, String>, the copies or why the image couldn't be read or written
+pub fn process_image(
+    source_path: &Path,
+    webpath: &str,
+    sha256: &str,
+    output_dir: &str,
+    options: &ImageOptions,
+) -> Result<ImageEntry, String> {
+    let (width, height) = match image::image_dimensions(source_path) {
+        Err(why) => return Err(format!("Image Read Error: {}", why)),
+        Ok(val) => val,
+    };
+    let short = &sha256[..HASH_LENGTH.min(sha256.len())];
+    let mut source: Option<DynamicImage> = None; // Only decoded when a copy has to be made
+
+    let mut widths: Vec<u32> = options
+        .widths
+        .iter()
+        .copied()
+        .filter(|x| *x > 0 && *x < width)
+        .collect();
+    widths.sort_unstable();
+    widths.dedup();
+    let mut variants: Vec<ImageVariant> = Vec::new();
+    for variant_width in widths {
+        let variant_height = scaled_height(width, height, variant_width);
+        let path = insert_before_extension(webpath, &format!("{}.{}w", short, variant_width));
+        let output = PathBuf::from(format!("{}{}", output_dir, path));
+        if !output.exists() {
+            let resized = decoded(&mut source, source_path)?.resize_exact(
+                variant_width,
+                variant_height,
+                FilterType::Lanczos3,
+            );
+            save_image(&resized, &output)?;
+        }
+        variants.push(ImageVariant {
+            path,
+            width: variant_width,
+            height: variant_height,
+        });
+    }
+
+    let thumbnail = insert_before_extension(
+        webpath,
+        &format!(
+            "{}.thumb{}x{}",
+            short, options.thumbnail_width, options.thumbnail_height
+        ),
+    );
+    let output = PathBuf::from(format!("{}{}", output_dir, thumbnail));
+    if !output.exists() {
+        let cropped = decoded(&mut source, source_path)?.resize_to_fill(
+            options.thumbnail_width.max(1),
+            options.thumbnail_height.max(1),
+            FilterType::Lanczos3,
+        );
+        save_image(&cropped, &output)?;
+    }
+
+    Ok(ImageEntry {
+        width,
+        height,
+        variants,
+        thumbnail,
+    })
+}
+
+/// Height of an image scaled to a width, keeping the aspect ratio
+pub fn scaled_height(width: u32, height: u32, new_width: u32) -> u32 {
+    let scaled = (height as f64 * new_width as f64 / width as f64).round() as u32;
+    scaled.max(1)
+}
+
+fn decoded<'a>(
+    source: &'a mut Option<DynamicImage>,
+    source_path: &Path,
+) -> Result<&'a DynamicImage, String> {
+    if source.is_none() {
+        match image::open(source_path) {
+            Err(why) => return Err(format!("Image Decode Error: {}", why)),
+            Ok(val) => *source = Some(val),
+        }
+    }
+    match source {
+        Some(val) => Ok(val),
+        None => Err(String::from("Image Decode Error")),
+    }
+}
+
+fn save_image(image: &DynamicImage, path: &Path) -> Result<(), String> {
+    if let Some(parent) = path.parent() {
+        if let Err(why) = std::fs::create_dir_all(parent) {
+            panic!("Couldn't create {}: {}", parent.to_string_lossy(), why);
+        }
+    }
+    match image.save(path) {
+        Err(why) => Err(format!(
+            "Couldn't write {}: {}",
+            path.to_string_lossy(),
+            why
+        )),
+        Ok(_) => Ok(()),
+    }
+}
+
+/// Give every <img> of a processed image a srcset, sizes, width and height
+///
+/// Parameters:
+///     html(&str), a rendered body
+///     manifest(&AssetManifest), the built assets and images
+///     sizes(&str), sizes attribute for tags without one
+/// Returns:
+///     String, the body with attributes the tags already have left as they are
+pub fn rewrite_images(html: &str, manifest: &AssetManifest, sizes: &str) -> String {
+    if manifest.images.is_empty() {
+        return html.to_string();
+    }
+    let mut rewritten = String::new();
+    let mut rest = html;
+    while let Some(start) = rest.find("<img") {
+        let end = match rest[start..].find('>') {
+            Some(val) => start + val,
+            None => break,
+        };
+        rewritten.push_str(&rest[..start]);
+        rewritten.push_str(&rewrite_image_tag(&rest[start..end], manifest, sizes));
+        rest = &rest[end..];
+    }
+    rewritten.push_str(rest);
+    rewritten
+}
+
+/// Add the attributes to one tag, given without its closing ">"
+fn rewrite_image_tag(tag: &str, manifest: &AssetManifest, sizes: &str) -> String {
+    let src = match attribute_value(tag, "src") {
+        Some(val) => val,
+        None => return tag.to_string(),
+    };
+    let entry = match manifest.images.get(&src) {
+        Some(val) => val,
+        None => return tag.to_string(),
+    };
+    let (body, closing) = match tag.strip_suffix('/') {
+        Some(val) => (val.trim_end(), " /"),
+        None => (tag, ""),
+    };
+    let mut attributes = String::new();
+    if attribute_value(tag, "srcset").is_none() && !entry.variants.is_empty() {
+        let mut candidates: Vec<String> = entry
+            .variants
+            .iter()
+            .map(|x| format!("{} {}w", x.path, x.width))
+            .collect();
+        let original = manifest.lookup(&src).unwrap_or(&src);
+        candidates.push(format!("{} {}w", original, entry.width));
+        attributes.push_str(&format!(" srcset=\"{}\"", candidates.join(", ")));
+        if attribute_value(tag, "sizes").is_none() && !sizes.is_empty() {
+            attributes.push_str(&format!(" sizes=\"{}\"", sizes));
+        }
+    }
+    if attribute_value(tag, "width").is_none() && attribute_value(tag, "height").is_none() {
+        attributes.push_str(&format!(
+            " width=\"{}\" height=\"{}\"",
+            entry.width, entry.height
+        ));
+    }
+    format!("{}{}{}", body, attributes, closing)
+}
+
+/// Value of a quoted attribute in a tag
+fn attribute_value(tag: &str, name: &str) -> Option<String> {
+    for quote in &['"', '\''] {
+        let needle = format!(" {}={}", name, quote);
+        if let Some(start) = tag.find(&needle) {
+            let value_start = start + needle.len();
+            let value_end = value_start + tag[value_start..].find(*quote)?;
+            return Some(tag[value_start..value_end].to_string());
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assets::ManifestEntry;
+
+    fn manifest() -> AssetManifest {
+        let mut manifest = AssetManifest::default();
+        manifest.assets.insert(
+            String::from("/static/a.png"),
+            ManifestEntry {
+                path: String::from("/static/a.123.png"),
+                sha256: String::from("123"),
+                size: 1,
+            },
+        );
+        manifest.images.insert(
+            String::from("/static/a.png"),
+            ImageEntry {
+                width: 1000,
+                height: 500,
+                variants: vec![ImageVariant {
+                    path: String::from("/static/a.123.480w.png"),
+                    width: 480,
+                    height: 240,
+                }],
+                thumbnail: String::from("/static/a.123.thumb240x240.png"),
+            },
+        );
+        manifest
+    }
+
+    #[test]
+    fn rewrites_image_tags() {
+        let html = "<p><img src=\"/static/a.png\" alt=\"A\" /><img src=\"/static/b.png\"></p>";
+        assert_eq!(
+            rewrite_images(html, &manifest(), "100vw"),
+            "<p><img src=\"/static/a.png\" alt=\"A\" srcset=\"/static/a.123.480w.png 480w, \
+             /static/a.123.png 1000w\" sizes=\"100vw\" width=\"1000\" height=\"500\" />\
+             <img src=\"/static/b.png\"></p>"
+        );
+        let sized = "<img width=\"20\" src='/static/a.png' sizes=\"50vw\">";
+        assert_eq!(
+            rewrite_images(sized, &manifest(), "100vw"),
+            "<img width=\"20\" src='/static/a.png' sizes=\"50vw\" srcset=\"/static/a.123.480w.png 480w, \
+             /static/a.123.png 1000w\">"
+        );
+    }
+
+    #[test]
+    fn makes_and_reuses_copies() {
+        let output_dir = std::env::temp_dir().join(format!("n4-images-{}", std::process::id()));
+        let source_path = output_dir.join("source.png");
+        std::fs::create_dir_all(&output_dir).unwrap();
+        DynamicImage::new_rgb8(100, 50).save(&source_path).unwrap();
+        let output = output_dir.to_string_lossy().to_string();
+        let options = ImageOptions {
+            widths: vec![200, 40],
+            thumbnail_width: 10,
+            thumbnail_height: 10,
+            ..ImageOptions::default()
+        };
+
+        let entry = process_image(&source_path, "/static/a.png", "abc", &output, &options).unwrap();
+        assert_eq!((entry.width, entry.height), (100, 50));
+        assert_eq!(
+            entry.variants,
+            vec![ImageVariant {
+                path: String::from("/static/a.abc.40w.png"),
+                width: 40,
+                height: 20,
+            }]
+        );
+        assert_eq!(entry.thumbnail, "/static/a.abc.thumb10x10.png");
+        let thumbnail = output_dir.join("static/a.abc.thumb10x10.png");
+        assert_eq!(image::image_dimensions(&thumbnail).unwrap(), (10, 10));
+
+        // A copy already in place isn't decoded or written again
+        let modified = std::fs::metadata(&thumbnail).unwrap().modified().unwrap();
+        process_image(&source_path, "/static/a.png", "abc", &output, &options).unwrap();
+        assert_eq!(
+            std::fs::metadata(&thumbnail).unwrap().modified().unwrap(),
+            modified
+        );
+        std::fs::remove_dir_all(&output_dir).unwrap();
+    }
+}
@@ -0,0 +1,231 @@
+//! Structured JSON content
+//!
+//! .json content files are parsed into a serde_json::Value and, depending on the ContentMeta.content_type of
+//! the page, validated against the shape that type expects and rendered to HTML.
+//!
+//! data-table
+//!     An array of objects, or an object with "columns" (array of strings) and "rows" (array of objects).
+//!     Rendered as a table, columns default to the keys of the rows in order of first appearance.
+//! card-list
+//!     An array of objects with a "title" and optional "description", "link" and "image".
+//!     Rendered as a list of cards.
+//!
+//! Any other content type is parsed but left for the template to render from JSONContent.body.
+use serde_json::{Map, Value};
+use v_htmlescape::escape;
+
+pub const JSON_TABLE: &str = "data-table";
+pub const JSON_CARDS: &str = "card-list";
+
+/// Parse the text of a .json file and validate it for the content type
+///
+/// Parameters:
+///     text(&str), the raw file contents
+///     content_type(&str), ContentMeta.content_type of the page
+/// Returns:
+///     (Value, Vec<String>), the parsed value (Null if it didn't parse) and any parse or validation errors
+pub fn parse_json_content(text: &str, content_type: &str) -> (Value, Vec<String>) {
+    match serde_json::from_str::<Value>(text) {
+        Err(why) => (Value::Null, vec![format!("JSON Parse Error: {}", why)]),
+        Ok(value) => {
+            let errors = validate_json_content(&value, content_type);
+            (value, errors)
+        }
+    }
+}
+
+/// Check a parsed value has the shape the content type needs, returns an empty Vec when it does
+pub fn validate_json_content(value: &Value, content_type: &str) -> Vec<String> {
+    let mut errors: Vec<String> = Vec::new();
+
+    match content_type {
+        JSON_TABLE => match table_rows(value) {
+            Some(rows) => {
+                for (index, row) in rows.iter().enumerate() {
+                    if !row.is_object() {
+                        errors.push(format!("Row {} is not an object", index));
+                    }
+                }
+            }
+            None => errors.push(String::from(
+                "A data-table needs an array of objects or an object with a rows array",
+            )),
+        },
+        JSON_CARDS => match value.as_array() {
+            Some(cards) => {
+                for (index, card) in cards.iter().enumerate() {
+                    match card.get("title") {
+                        Some(Value::String(_)) => {}
+                        _ => errors.push(format!("Card {} is missing a string title", index)),
+                    }
+                }
+            }
+            None => errors.push(String::from("A card-list needs an array of objects")),
+        },
+        _ => {}
+    }
+
+    errors
+}
+
+/// Render a parsed value to HTML for the JSON specific content types, None for anything else
+pub fn render_json_content(value: &Value, content_type: &str) -> Option<String> {
+    match content_type {
+        JSON_TABLE => table_rows(value).map(|rows| render_table(value, rows)),
+        JSON_CARDS => value.as_array().map(|cards| render_cards(cards)),
+        _ => None,
+    }
+}
+
+fn table_rows(value: &Value) -> Option<&Vec<Value>> {
+    match value {
+        Value::Array(rows) => Some(rows),
+        Value::Object(table) => table.get("rows").and_then(|x| x.as_array()),
+        _ => None,
+    }
+}
+
+fn table_columns(value: &Value, rows: &[Value]) -> Vec<String> {
+    if let Some(columns) = value.get("columns").and_then(|x| x.as_array()) {
+        return columns.iter().map(cell_text).collect::<Vec<String>>();
+    }
+    let mut columns: Vec<String> = Vec::new();
+    for row in rows {
+        if let Some(row) = row.as_object() {
+            for key in row.keys() {
+                if !columns.contains(key) {
+                    columns.push(key.clone());
+                }
+            }
+        }
+    }
+    columns
+}
+
+fn render_table(value: &Value, rows: &[Value]) -> String {
+    let columns = table_columns(value, rows);
+    let empty = Map::new();
+    let mut html = String::from("<table class=\"json-table\">\n<thead>\n<tr>");
+    for column in &columns {
+        html.push_str(&format!("<th>{}</th>", escape(column)));
+    }
+    html.push_str("</tr>\n</thead>\n<tbody>\n");
+    for row in rows {
+        let row = row.as_object().unwrap_or(&empty);
+        html.push_str("<tr>");
+        for column in &columns {
+            let cell = row.get(column).map(cell_text).unwrap_or_default();
+            html.push_str(&format!("<td>{}</td>", escape(&cell)));
+        }
+        html.push_str("</tr>\n");
+    }
+    html.push_str("</tbody>\n</table>\n");
+    html
+}
+
+fn render_cards(cards: &[Value]) -> String {
+    let mut html = String::from("<ul class=\"json-cards\">\n");
+    for card in cards {
+        let field = |name: &str| card.get(name).map(cell_text).unwrap_or_default();
+        let title = field("title");
+        let link = safe_url(&field("link"));
+        let image = safe_url(&field("image"));
+        let description = field("description");
+
+        html.push_str("<li class=\"json-card\">");
+        if !image.is_empty() {
+            html.push_str(&format!(
+                "<img src=\"{}\" alt=\"{}\">",
+                escape(&image),
+                escape(&title)
+            ));
+        }
+        if link.is_empty() {
+            html.push_str(&format!("<h3>{}</h3>", escape(&title)));
+        } else {
+            html.push_str(&format!(
+                "<h3><a href=\"{}\">{}</a></h3>",
+                escape(&link),
+                escape(&title)
+            ));
+        }
+        if !description.is_empty() {
+            html.push_str(&format!("<p>{}</p>", escape(&description)));
+        }
+        html.push_str("</li>\n");
+    }
+    html.push_str("</ul>\n");
+    html
+}
+
+/// URL schemes a card link or image may use, relative URLs are always fine
+const CARD_URL_SCHEMES: [&str; 3] = ["http", "https", "mailto"];
+
+/// The URL if it is relative or uses an allowed scheme, otherwise empty so "javascript:" and friends never
+/// reach an href or src
+fn safe_url(url: &str) -> String {
+    let url = url.trim();
+    let scheme_end = match url.find([':', '/', '?', '#']) {
+        Some(val) if url[val..].starts_with(':') => val,
+        _ => return url.to_string(), // No scheme
+    };
+    // Browsers ignore whitespace and control characters inside a scheme, "java\tscript:"
+    let scheme: String = url[..scheme_end]
+        .chars()
+        .filter(|c| !c.is_whitespace() && !c.is_control())
+        .collect::<String>()
+        .to_ascii_lowercase();
+    if CARD_URL_SCHEMES.contains(&scheme.as_str()) {
+        url.to_string()
+    } else {
+        String::new()
+    }
+}
+
+fn cell_text(value: &Value) -> String {
+    match value {
+        Value::Null => String::new(),
+        Value::String(text) => text.clone(),
+        other => other.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_tables_from_rows() {
+        let (value, errors) =
+            parse_json_content(r#"[{"name": "a<b", "size": 2}, {"name": "c"}]"#, JSON_TABLE);
+        assert!(errors.is_empty());
+        let html = render_json_content(&value, JSON_TABLE).unwrap();
+        assert!(html.contains("<th>name</th><th>size</th>"));
+        assert!(html.contains("<td>a&lt;b</td><td>2</td>"));
+        assert!(html.contains("<td>c</td><td></td>"));
+    }
+
+    #[test]
+    fn validates_cards() {
+        let (_, errors) = parse_json_content(r#"[{"title": "ok"}, {"link": "/x"}]"#, JSON_CARDS);
+        assert_eq!(
+            errors,
+            vec![String::from("Card 1 is missing a string title")]
+        );
+        let (value, errors) = parse_json_content(
+            r#"[{"title": "x", "link": "javascript:alert(1)", "image": "Java\tScript:x"},
+                {"title": "y", "link": "/y", "image": "https://e.com/y.png"}]"#,
+            JSON_CARDS,
+        );
+        assert!(errors.is_empty());
+        let html = render_json_content(&value, JSON_CARDS).unwrap();
+        assert!(!html.to_lowercase().contains("script:"));
+        assert!(html.contains("<h3>x</h3>"));
+        assert_eq!(html.matches("<img src=").count(), 1);
+        assert_eq!(html.matches("<a href=").count(), 1);
+
+        let (value, errors) = parse_json_content("{not json", JSON_CARDS);
+        assert_eq!(value, Value::Null);
+        assert_eq!(errors.len(), 1);
+    }
+}
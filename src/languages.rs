@@ -0,0 +1,274 @@
+//! Multilingual content
+//!
+//! With default_language and languages in the config content can be published in several languages.  Files in
+//! the default language keep their plain names, a translation puts the language code before the extension:
+//! "page.es.md" with "page.es.content_meta" is the Spanish version of "page".  A page, section meta or menu
+//! without a file for the requested language falls back to the default language one, so every page needs a
+//! default language file and translations without one aren't listed.  Directories translate their menu entry
+//! and section settings with "blog.es.menu_meta".
+//!
+//! The default language keeps the plain web paths, other languages are prefixed with their code,
+//! "/es/blog/first".  split_language_prefix() takes a requested web path apart for routing.
+//!
+//! read_single_page_lang() marks the language as current while the page loads, content lists, includes and
+//! section metas read further down use the same language.
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+use serde_derive::{Deserialize, Serialize};
+
+use crate::{
+    content_file_path, content_path_with_extension, load_config, read_file_modified_time,
+    SiteMapEntry,
+};
+
+/// hreflang value of the alternate a search engine should use for languages the site doesn't have
+pub const X_DEFAULT: &str = "x-default";
+
+/// Another language version of a sitemap entry
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SiteMapAlternate {
+    pub hreflang: String,
+    pub href: String,
+}
+
+/// The default language of the site, empty when the site isn't multilingual
+pub fn default_language() -> String {
+    load_config().default_language
+}
+
+/// Every language of the site with the default first, empty when the site isn't multilingual
+pub fn site_languages() -> Vec<String> {
+    let config = load_config();
+    if config.default_language.is_empty() {
+        return Vec::new();
+    }
+    let mut languages = vec![config.default_language.clone()];
+    for language in config.languages {
+        if !languages.contains(&language) {
+            languages.push(language);
+        }
+    }
+    languages
+}
+
+/// Split a file stem into the page stem and its language code, None if it has no site language suffix
+///
+/// Parameters:
+///     stem(&str), a file name without its extension such as "page.es"
+///     languages(&[String]), from site_languages()
+/// Returns:
+///     Option<(&str, &str)>, the page stem and the language, ("page", "es")
+pub fn language_of_stem<'a>(stem: &'a str, languages: &[String]) -> Option<(&'a str, &'a str)> {
+    let dot = stem.rfind('.')?;
+    let (base, language) = (&stem[..dot], &stem[dot + 1..]);
+    if base.is_empty() || !languages.iter().any(|x| x == language) {
+        return None;
+    }
+    Some((base, language))
+}
+
+/// Is a file stem a translation of another page
+pub fn is_language_variant(stem: &str) -> bool {
+    language_of_stem(stem, &site_languages()).is_some()
+}
+
+/// The extensionless local path of a page in a language, the default language path when there's no translation
+pub fn localized_local_path(local_path: &str, language: &str) -> String {
+    if language.is_empty() {
+        return local_path.to_string();
+    }
+    let variant = format!("{}.{}", local_path.trim_end_matches('/'), language);
+    if content_file_path(&variant).is_some() {
+        variant
+    } else {
+        local_path.to_string()
+    }
+}
+
+/// The extensionless local path of the page a translation belongs to, None if the path isn't a translation
+pub fn untranslated_local_path(local_path: &str) -> Option<String> {
+    let name_start = local_path.rfind('/').map(|x| x + 1).unwrap_or(0);
+    let (base, _) = language_of_stem(&local_path[name_start..], &site_languages())?;
+    Some(format!("{}{}", &local_path[..name_start], base))
+}
+
+/// The .menu_meta file of a directory in a language, the default language one when there's no translation
+pub fn localized_menu_meta_path(dir_path: &str, language: &str) -> PathBuf {
+    let dir_path = dir_path.trim_end_matches('/');
+    if !language.is_empty() {
+        let variant =
+            content_path_with_extension(&format!("{}.{}", dir_path, language), "menu_meta");
+        if variant.exists() {
+            return variant;
+        }
+    }
+    content_path_with_extension(dir_path, "menu_meta")
+}
+
+/// Languages a page has content in, the default language first
+pub fn page_languages(local_path: &str) -> Vec<String> {
+    let mut languages: Vec<String> = Vec::new();
+    for language in site_languages() {
+        let variant = format!("{}.{}", local_path.trim_end_matches('/'), language);
+        if languages.is_empty() || content_file_path(&variant).is_some() {
+            languages.push(language); // The default language always has the plain file
+        }
+    }
+    languages
+}
+
+/// Web path of a page in a language, the default language keeps it as is
+pub fn localized_webpath(webpath: &str, language: &str) -> String {
+    if language.is_empty() || language == default_language() {
+        return webpath.to_string();
+    }
+    format!("/{}{}", language, webpath)
+}
+
+/// Take the language prefix off a requested web path
+///
+/// Parameters:
+///     webpath(&str), such as "/es/blog/first"
+/// Returns:
+///     (String, String), the language and the web path without it, the default language and the web path
+///     unchanged when it has no prefix
+pub fn split_language_prefix(webpath: &str) -> (String, String) {
+    split_language_prefix_in(webpath, &site_languages())
+}
+
+/// split_language_prefix() with the languages given, the default first like site_languages() has them
+pub fn split_language_prefix_in(webpath: &str, languages: &[String]) -> (String, String) {
+    let default = languages.first().cloned().unwrap_or_default();
+    let trimmed = webpath.trim_start_matches('/');
+    let first = trimmed.split('/').next().unwrap_or("");
+    if !first.is_empty() && first != default && languages.iter().any(|x| x == first) {
+        let rest = &trimmed[first.len()..];
+        let rest = if rest.is_empty() { "/" } else { rest };
+        return (first.to_string(), rest.to_string());
+    }
+    (default, webpath.to_string())
+}
+
+/// Web paths of every language version of a page, keyed by language
+pub fn page_translations(local_path: &str, webpath: &str) -> BTreeMap<String, String> {
+    page_languages(local_path)
+        .into_iter()
+        .map(|language| {
+            let localized = localized_webpath(webpath, &language);
+            (language, localized)
+        })
+        .collect()
+}
+
+/// One sitemap entry per language version of a page, each listing all of them as alternates
+///
+/// Parameters:
+///     entry(SiteMapEntry), the default language entry
+///     local_path(&str), extensionless local path of the page
+///     prod_host(&str), the config prod_host the entry location starts with
+/// Returns:
+///     Vec<SiteMapEntry>, just the entry when the site isn't multilingual
+pub fn language_sitemap_entries(
+    entry: SiteMapEntry,
+    local_path: &str,
+    prod_host: &str,
+) -> Vec<SiteMapEntry> {
+    let languages = page_languages(local_path);
+    let webpath = match entry.location.strip_prefix(prod_host) {
+        Some(val) if !languages.is_empty() => val.to_string(),
+        _ => return vec![entry],
+    };
+    let mut alternates: Vec<SiteMapAlternate> = languages
+        .iter()
+        .map(|language| SiteMapAlternate {
+            hreflang: language.clone(),
+            href: format!("{}{}", prod_host, localized_webpath(&webpath, language)),
+        })
+        .collect();
+    alternates.push(SiteMapAlternate {
+        hreflang: String::from(X_DEFAULT),
+        href: entry.location.clone(),
+    });
+
+    let mut entries: Vec<SiteMapEntry> = Vec::new();
+    for (index, language) in languages.iter().enumerate() {
+        let lastmod = if index == 0 {
+            entry.lastmod
+        } else {
+            match content_file_path(&format!("{}.{}", local_path, language)) {
+                Some(path) => read_file_modified_time(&path),
+                None => entry.lastmod,
+            }
+        };
+        entries.push(SiteMapEntry {
+            location: alternates[index].href.clone(),
+            lastmod,
+            priority: entry.priority.clone(),
+            alternates: alternates.clone(),
+        });
+    }
+    entries
+}
+
+thread_local! {
+    // Languages of the pages and listings being read on this thread, innermost last
+    static CURRENT: RefCell<Vec<String>> = RefCell::default();
+}
+
+/// Marks a language as current until the guard is dropped
+pub struct LanguageGuard;
+
+impl Drop for LanguageGuard {
+    fn drop(&mut self) {
+        CURRENT.with(|x| x.borrow_mut().pop());
+    }
+}
+
+/// Read content in a language until the returned guard is dropped, empty means the default language
+pub fn enter_language(language: &str) -> LanguageGuard {
+    CURRENT.with(|x| x.borrow_mut().push(language.to_string()));
+    LanguageGuard
+}
+
+/// The language content is being read in, the default language outside of any enter_language()
+pub fn current_language() -> String {
+    match CURRENT.with(|x| x.borrow().last().cloned()) {
+        Some(language) if !language.is_empty() => language,
+        _ => default_language(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn splits_language_stems() {
+        let languages = vec![String::from("en"), String::from("es")];
+        assert_eq!(
+            language_of_stem("page.es", &languages),
+            Some(("page", "es"))
+        );
+        assert_eq!(
+            language_of_stem("v1.2.en", &languages),
+            Some(("v1.2", "en"))
+        );
+        assert_eq!(language_of_stem("page.fr", &languages), None);
+        assert_eq!(language_of_stem("page", &languages), None);
+        assert_eq!(language_of_stem(".es", &languages), None);
+    }
+
+    #[test]
+    fn nests_current_languages() {
+        let outer = enter_language("es");
+        assert_eq!(current_language(), "es");
+        {
+            let _inner = enter_language("fr");
+            assert_eq!(current_language(), "fr");
+        }
+        assert_eq!(current_language(), "es");
+        drop(outer);
+    }
+}
@@ -5,7 +5,8 @@
 /// Picking back up after quite a bit of time away from this.
 extern crate dotenv;
 
-use std::collections::HashMap;
+use std::cell::Cell;
+use std::collections::{BTreeMap, HashMap};
 use std::fs;
 use std::io::prelude::*;
 use std::path::{Path, PathBuf};
@@ -23,6 +24,61 @@ use v_htmlescape::escape;
 // Currently a development dependency, probably going to move to a module
 use file_tree::*;
 
+pub mod assets;
+pub mod backlinks;
+pub mod bundles;
+pub mod check;
+pub mod content_list;
+pub mod formats;
+pub mod git_history;
+pub mod images;
+pub mod json_content;
+pub mod languages;
+pub mod markup;
+pub mod meta_migrations;
+pub mod meta_repair;
+pub mod meta_schema;
+pub mod page_bundles;
+pub mod page_dates;
+pub mod pagination;
+pub mod publication;
+pub mod query;
+pub mod sanitize;
+pub mod shortcodes;
+pub mod sorting;
+pub mod table_content;
+pub mod taxonomy;
+
+use assets::{asset_url, rewrite_page_assets};
+use backlinks::Backlink;
+use content_list::{enter_page, expand_content_list};
+use formats::{
+    content_extensions, is_content_extension, read_formatted_content, uses_core_reader,
+    FormattedContent,
+};
+use git_history::git_file_history;
+use images::ImageOptions;
+use json_content::{parse_json_content, render_json_content};
+use languages::{
+    current_language, enter_language, is_language_variant, language_sitemap_entries,
+    localized_local_path, localized_menu_meta_path, localized_webpath, page_translations,
+    untranslated_local_path, SiteMapAlternate,
+};
+use meta_migrations::{migrate_meta, MetaKind, CURRENT_SCHEMA_VERSION};
+use meta_repair::salvage_json;
+use meta_schema::{tolerant_content_meta, tolerant_menu_meta};
+use page_bundles::{bundle_index_path, bundle_of, is_page_bundle, resolve_page_bundle};
+use page_dates::{resolve_page_dates, PageDates};
+use pagination::{paginate, pagination_sitemap_entries, Paginated};
+use publication::{
+    content_is_visible, local_content_is_visible, menu_is_visible, STATUS_PUBLISHED,
+};
+use sanitize::{sanitize_page, SanitizePolicy};
+use shortcodes::{extract_shortcodes, restore_shortcodes};
+use sorting::{content_tiebreak, sort_content_metas, sorted_menu_keys};
+use table_content::TableOptions;
+use taxonomy::{generate_taxonomy_index, taxonomy_sitemap_entries};
+
 /// Struct to hold the site configuration
 ///
 /// prod_host
@@ -33,12 +89,45 @@ use file_tree::*;
 ///     content-data: Relative root directory name of the content
 /// local_content_dir
 ///     content-data: Absolute path to content directory, concatenated with base dir on end
+/// static_dir
+///     content-data: Absolute path to the directory served as /static/, when empty /static/ is looked up
+///     under local_content_dir
+/// git_history
+///     content-data: Use the first and last git commit of content files for dates instead of file system times
+/// preview
+///     content-data: List drafts, scheduled, expired and archived content as well, for local previews
+/// output_dir
+///     build-data: Absolute path builds are written to, static assets go in its static/ directory, see assets
+/// bundle_assets
+///     build-data: Concatenate and minify the css and javascript of each page into shared bundles, see bundles
+/// images
+///     build-data: Resized copies and thumbnails of static images, see images
+/// default_language
+///     content-data: Language code of the plain content files such as "en", empty for a single language site
+/// languages
+///     content-data: Codes of the other languages content is translated to, see languages
 #[derive(Serialize, Deserialize, Debug)]
 pub struct SiteConfig {
     pub prod_host: String,
     pub xml_priority: String,
     pub base_dir: String,
     pub local_content_dir: String,
+    #[serde(default)]
+    pub static_dir: String,
+    #[serde(default)]
+    pub git_history: bool,
+    #[serde(default)]
+    pub preview: bool,
+    #[serde(default)]
+    pub output_dir: String,
+    #[serde(default)]
+    pub bundle_assets: bool,
+    #[serde(default)]
+    pub images: ImageOptions,
+    #[serde(default)]
+    pub default_language: String,
+    #[serde(default)]
+    pub languages: Vec<String>,
 }
 
 impl SiteConfig {
@@ -55,6 +144,38 @@ pub struct PageContent {
     pub list: Vec<PageContent>,
     pub meta: ContentMeta,
     pub section_meta: MenuItemMeta,
+    #[serde(default)]
+    pub backlinks: Vec<Backlink>, // Filled in by a BacklinkIndex, see backlinks::generate_backlinks()
+    #[serde(default)]
+    pub assets: EffectiveAssets,
+    #[serde(default)]
+    pub dates: PageDates, // Picked by meta created_time_default and modified_time_default
+    #[serde(default)]
+    pub formatted: Vec<FormattedContent>, // Files in registered formats other than md, html and json
+    #[serde(default)]
+    pub language: String, // Language the page was read in, empty for a single language site
+    #[serde(default)]
+    pub translations: BTreeMap<String, String>, // Web path of each language version, see languages
+}
+
+/// The body classes, javascript and css a page ends up with once section inheritance is applied.
+/// Includes are in load order, section includes first, with duplicates removed.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct EffectiveAssets {
+    pub classes: Vec<String>,
+    pub javascript_include: Vec<String>,
+    pub javascript_inline: String,
+    pub css_include: Vec<String>,
+    pub css_inline: String,
+}
+
+/// Append includes skipping blanks and anything already in the list
+fn merge_includes(merged: &mut Vec<String>, includes: &[String]) {
+    for include in includes {
+        if !include.is_empty() && !merged.contains(include) {
+            merged.push(include.clone());
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -99,12 +220,22 @@ impl Default for HTMLContent {
     }
 }
 
+/// Parsed .json content, see the json_content module for the content types that get rendered
+///
+/// body
+///     The parsed file, Null if it failed to parse
+/// errors
+///     Parse errors and validation errors against the page content_type
+/// rendered
+///     HTML for JSON specific content types such as "data-table" and "card-list"
 #[derive(Serialize, Deserialize, Debug)]
 pub struct JSONContent {
     pub created: chrono::DateTime<chrono::Utc>,
     pub modified: chrono::DateTime<chrono::Utc>,
     // pub path: String,
-    pub body: String,
+    pub body: serde_json::Value,
+    pub errors: Vec<String>,
+    pub rendered: Option<String>,
 }
 
 impl Default for JSONContent {
@@ -113,13 +244,19 @@ impl Default for JSONContent {
             created: unix_time_to_iso(0.0),
             modified: unix_time_to_iso(0.0),
             // path: String::from("/"),
-            body: String::from("None"),
+            body: serde_json::Value::Null,
+            errors: Vec::new(),
+            rendered: None,
         }
     }
 }
 
+/// Metadata for a piece of content, stored as JSON in a .content_meta sidecar file.
+/// See schemas/content_meta.schema.json, missing fields take their default value.
 #[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
 pub struct ContentMeta {
+    pub schema_version: u32,
     pub title: String,
     pub path: String,
     pub content_icon: String,
@@ -129,19 +266,28 @@ pub struct ContentMeta {
     pub license: String,
     pub content_list: Vec<String>,
     pub content_type: String,
-    content_class: String,
+    pub content_class: String,
     pub template_override: String,
-    javascript_include: Vec<String>,
-    javascript_inline: String,
-    css_include: Vec<String>,
-    css_inline: String,
-    created_time_default: String,
-    modified_time_default: String,
+    pub javascript_include: Vec<String>,
+    pub javascript_inline: String,
+    pub css_include: Vec<String>,
+    pub css_inline: String,
+    pub created_time_default: String, // markdown, html, json, meta, git or explicit, see page_dates
+    pub modified_time_default: String,
+    pub created_time: String, // Only used when created_time_default is explicit
+    pub modified_time: String, // Only used when modified_time_default is explicit
+    pub status: String,       // draft, published or archived, see publication
+    pub publish_at: String,   // Not listed before this date when set
+    pub expire_at: String,    // Not listed from this date on when set
+    pub tags: Vec<String>,    // Listed on /tags/<slug>, see taxonomy
+    pub categories: Vec<String>, // Listed on /categories/<slug>
+    pub table: TableOptions,  // How .csv and .tsv content is shown, see table_content
 }
 
 impl Default for ContentMeta {
     fn default() -> Self {
         ContentMeta {
+            schema_version: CURRENT_SCHEMA_VERSION,
             title: String::from("Default ContentMeta struct title"),
             path: String::from("/"),
             content_icon: String::from("/static/images/content_default_icon.svg"),
@@ -159,10 +305,47 @@ impl Default for ContentMeta {
             css_inline: String::from(""),
             created_time_default: String::from("markdown"),
             modified_time_default: String::from("markdown"),
+            created_time: String::from(""),
+            modified_time: String::from(""),
+            status: String::from(STATUS_PUBLISHED),
+            publish_at: String::from(""),
+            expire_at: String::from(""),
+            tags: Vec::new(),
+            categories: Vec::new(),
+            table: TableOptions::default(),
         }
     }
 }
 
+impl ContentMeta {
+    /// Merge the inherited section classes and includes of the directory with the ones of this content
+    ///
+    /// Parameters:
+    ///     section_meta(&MenuItemMeta), the meta of the directory holding the content
+    /// Returns:
+    ///     EffectiveAssets, what the page template should load
+    pub fn effective_assets(&self, section_meta: &MenuItemMeta) -> EffectiveAssets {
+        let mut assets = EffectiveAssets::default();
+        merge_includes(
+            &mut assets.classes,
+            &[
+                section_meta.section_class.clone(),
+                self.content_class.clone(),
+            ],
+        );
+        merge_includes(
+            &mut assets.javascript_include,
+            &section_meta.section_javascript_include,
+        );
+        merge_includes(&mut assets.javascript_include, &self.javascript_include);
+        merge_includes(&mut assets.css_include, &section_meta.section_css_include);
+        merge_includes(&mut assets.css_include, &self.css_include);
+        assets.javascript_inline = self.javascript_inline.clone();
+        assets.css_inline = self.css_inline.clone();
+        assets
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct DirContent {
     modified: chrono::DateTime<chrono::Utc>, //NaiveDateTime,
@@ -175,6 +358,8 @@ pub struct SiteMapEntry {
     pub location: String,
     pub lastmod: DateTime<Utc>,
     pub priority: String,
+    #[serde(default)]
+    pub alternates: Vec<SiteMapAlternate>, // hreflang versions on a multilingual site
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -183,6 +368,8 @@ pub struct MenuItem {
     number_of_files: u32,
     relative_path: String,
     children: HashMap<String, MenuItem>,
+    #[serde(default)]
+    child_order: Vec<String>, // Keys of children in the order set by sort_by and sort_order of menu_meta
 }
 
 impl Default for MenuItem {
@@ -192,31 +379,65 @@ impl Default for MenuItem {
             number_of_files: 0,
             relative_path: "Default".to_string(),
             children: HashMap::new(),
+            child_order: Vec::new(),
         }
     }
 }
 
+/// Metadata for a directory, stored as JSON in a .menu_meta file next to the directory.
+/// See schemas/menu_meta.schema.json, missing fields take their default value.
 #[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
 pub struct MenuItemMeta {
+    pub schema_version: u32,
     pub menu_icon: String,   // Really a path to an svg
     pub description: String, // Used in title attribute for hover detail
     pub weight: u32,
     pub section_template: String, // This is intended to be a new default for all content in the directory
     pub template_override: String, // This override is for just the index page of the directory
     pub content_type: String,
-    section_class: String,                   // This is an inherited body class
-    content_class: String,                   // Not inherited, just for the directory index page
-    section_javascript_include: Vec<String>, // Inherited
-    javascript_include: Vec<String>,
-    javascript_inline: String,
-    section_css_include: Vec<String>, // Inherited
-    css_include: Vec<String>,
-    css_inline: String,
+    pub section_class: String, // This is an inherited body class
+    pub content_class: String, // Not inherited, just for the directory index page
+    pub section_javascript_include: Vec<String>, // Inherited
+    pub javascript_include: Vec<String>,
+    pub javascript_inline: String,
+    pub section_css_include: Vec<String>, // Inherited
+    pub css_include: Vec<String>,
+    pub css_inline: String,
+    pub status: String, // draft, published or archived, unpublished directories are left out of menus
+    pub publish_at: String, // Not shown before this date when set
+    pub expire_at: String, // Not shown from this date on when set
+    pub page_size: u32, // Listing entries per page for the directory and its content lists, 0 for no paging
+    pub sort_by: String, // Listing and submenu order, one of sorting::sortable_fields()
+    pub sort_order: String, // ascending or descending, see sorting
+    pub sanitize: SanitizePolicy, // Content directly in the directory, subdirectories set their own
+}
+
+impl MenuItemMeta {
+    /// The classes and includes for the index page of the directory itself, section values first
+    pub fn index_assets(&self) -> EffectiveAssets {
+        let mut assets = EffectiveAssets::default();
+        merge_includes(
+            &mut assets.classes,
+            &[self.section_class.clone(), self.content_class.clone()],
+        );
+        merge_includes(
+            &mut assets.javascript_include,
+            &self.section_javascript_include,
+        );
+        merge_includes(&mut assets.javascript_include, &self.javascript_include);
+        merge_includes(&mut assets.css_include, &self.section_css_include);
+        merge_includes(&mut assets.css_include, &self.css_include);
+        assets.javascript_inline = self.javascript_inline.clone();
+        assets.css_inline = self.css_inline.clone();
+        assets
+    }
 }
 
 impl Default for MenuItemMeta {
     fn default() -> Self {
         MenuItemMeta {
+            schema_version: CURRENT_SCHEMA_VERSION,
             menu_icon: String::from("/static/images/menu_default_icon.svg"),
             description: String::from("Menu default description."),
             weight: 100,
@@ -231,6 +452,13 @@ impl Default for MenuItemMeta {
             section_css_include: Vec::new(),
             css_include: Vec::new(),
             css_inline: String::from(""),
+            status: String::from(STATUS_PUBLISHED),
+            publish_at: String::from(""),
+            expire_at: String::from(""),
+            page_size: 0,
+            sort_by: String::from("weight"),
+            sort_order: String::from("ascending"),
+            sanitize: SanitizePolicy::default(),
         }
     }
 }
@@ -286,6 +514,14 @@ pub fn setup_config() {
             xml_priority: String::from("0.64"),
             base_dir: String::from("/"),
             local_content_dir: String::from("/"),
+            static_dir: String::from(""),
+            git_history: false,
+            preview: false,
+            output_dir: String::from(""),
+            bundle_assets: false,
+            images: ImageOptions::default(),
+            default_language: String::from(""),
+            languages: Vec::new(),
         };
         let mut file = match fs::File::create(config_dir) {
             Err(why) => panic!("File creation fail: {}", why),
@@ -323,20 +559,50 @@ pub fn read_menu_meta_file(file_path: PathBuf) -> MenuItemMeta {
         Err(why) => panic!("Couldn't open file: {}", why),
         Ok(mut _file) => _file.read_to_string(&mut content),
     };
-    // Deserialize the JSON
-    let return_struct: MenuItemMeta = match serde_json::from_str(&content) {
+    // Deserialize the JSON, salvaging what a hand edit broke, upgrade it from older schema versions, fields
+    // that fail validation fall back to defaults
+    let parsed: Option<serde_json::Value> = match serde_json::from_str(&content) {
         Err(why) => {
-            println!("Bad menu meta JSON: {} \n {:#?}", why, content); // TODO Change to logging
-            return MenuItemMeta::default();
+            // TODO Change to logging
+            println!(
+                "Menu meta {} JSON Parse Error: {}, n4 fix-meta can repair it",
+                file_path.to_string_lossy(),
+                why
+            );
+            salvage_json(&content)
+        }
+        Ok(value) => Some(value),
+    };
+    let return_struct: MenuItemMeta = match parsed {
+        None => MenuItemMeta::default(),
+        Some(value) => {
+            let value = match migrate_meta(value.clone(), MetaKind::Menu) {
+                Ok((migrated, _)) => migrated,
+                Err(why) => {
+                    // TODO Change to logging
+                    println!("Menu meta {} {}", file_path.to_string_lossy(), why);
+                    value // Validation strips the version it can't use
+                }
+            };
+            let (menu_meta, errors) = tolerant_menu_meta(value);
+            for error in errors {
+                // TODO Change to logging
+                println!(
+                    "Menu meta {} field {} {}, using the default",
+                    file_path.to_string_lossy(),
+                    error.path,
+                    error.message
+                );
+            }
+            menu_meta
         }
-        Ok(value) => value,
     };
     return_struct
 }
 
 // Formats a path to a directory for the .menu_meta extension and checks if it exists
-pub fn add_menu_metadata(meta_path_raw: &String) -> MenuItemMeta {
-    let meta_path: PathBuf = PathBuf::from(&format!("{}{}", meta_path_raw, ".menu_meta"));
+pub fn add_menu_metadata(meta_path_raw: &str) -> MenuItemMeta {
+    let meta_path: PathBuf = localized_menu_meta_path(meta_path_raw, &current_language());
 
     if meta_path.exists() {
         read_menu_meta_file(meta_path)
@@ -345,7 +611,21 @@ pub fn add_menu_metadata(meta_path_raw: &String) -> MenuItemMeta {
     }
 }
 
+/// The menus in a language, titles and settings come from the translated .menu_meta files when there are
+/// any and the paths of other languages than the default are prefixed with the language
+///
+/// Parameters:
+///     dir_tree(DirTree), usually from generate_content_state()
+///     language(&str), language code, empty for the default language
+/// Returns:
+///     HashMap<String, MenuItem>, the same as tree_to_menus()
+pub fn tree_to_menus_lang(dir_tree: DirTree, language: &str) -> HashMap<String, MenuItem> {
+    let _language = enter_language(language);
+    tree_to_menus(dir_tree)
+}
+
 pub fn tree_to_menus(dir_tree: DirTree) -> HashMap<String, MenuItem> {
+    let language = current_language();
     let mut menus: HashMap<String, MenuItem> = HashMap::new();
     let config = load_config();
     let prefix_to_strip = match config.base_dir.strip_suffix("/") {
@@ -353,32 +633,43 @@ pub fn tree_to_menus(dir_tree: DirTree) -> HashMap<String, MenuItem> {
         _ => panic!("Base dir is missing the trailing directory delimiter."),
     };
     for (key, value) in dir_tree.directories {
+        if is_page_bundle(Path::new(&value.absolute_path)) {
+            continue; // A page, not a section
+        }
+        let mut menu_meta = add_menu_metadata(&value.absolute_path);
+        if !menu_is_visible(&menu_meta) {
+            continue; // Unpublished directories take their children with them
+        }
+        menu_meta.menu_icon = asset_url(&menu_meta.menu_icon);
         if value.directories.len() > 0 {
+            let relative_path = localized_webpath(
+                value.relative_path.strip_prefix(prefix_to_strip).unwrap(),
+                &language,
+            );
+            let number_of_files = value.files.len() as u32;
+            let children = tree_to_menus(value); // Recursion
             menus.insert(
                 key,
                 MenuItem {
-                    menu_meta: add_menu_metadata(&value.absolute_path),
-                    number_of_files: value.files.len() as u32,
-                    relative_path: value
-                        .relative_path
-                        .strip_prefix(prefix_to_strip)
-                        .unwrap()
-                        .to_string(),
-                    children: tree_to_menus(value), // Recursion
+                    child_order: sorted_menu_keys(&children, &menu_meta),
+                    menu_meta,
+                    number_of_files,
+                    relative_path,
+                    children,
                 },
             );
         } else {
             menus.insert(
                 key,
                 MenuItem {
-                    menu_meta: add_menu_metadata(&value.absolute_path),
+                    menu_meta,
                     number_of_files: value.files.len() as u32,
-                    relative_path: value
-                        .relative_path
-                        .strip_prefix(prefix_to_strip)
-                        .unwrap()
-                        .to_string(),
+                    relative_path: localized_webpath(
+                        value.relative_path.strip_prefix(prefix_to_strip).unwrap(),
+                        &language,
+                    ),
                     children: HashMap::new(), // Blank default
+                    child_order: Vec::new(),
                 },
             );
         }
@@ -386,6 +677,18 @@ pub fn tree_to_menus(dir_tree: DirTree) -> HashMap<String, MenuItem> {
     menus
 }
 
+/// Display order of the top level of tree_to_menus(), nested levels carry theirs in MenuItem.child_order
+///
+/// Parameters:
+///     menus(&HashMap<String, MenuItem>), the result of tree_to_menus() or tree_to_menus_lang()
+/// Returns:
+///     Vec<String>, the keys of menus sorted by the sort_by and sort_order of the content directory's own
+///     .menu_meta ("content.menu_meta" next to the content directory), by weight without one
+pub fn root_menu_order(menus: &HashMap<String, MenuItem>) -> Vec<String> {
+    let root_meta = add_menu_metadata(&load_config().local_path());
+    sorted_menu_keys(menus, &root_meta)
+}
+
 // Oh the things we do to get the correct ISO timestamps
 pub fn unix_time_to_iso(timestamp: f64) -> chrono::DateTime<chrono::Utc> {
     let converted_timestamp: i64 = timestamp as i64;
@@ -400,6 +703,17 @@ fn tree_to_sitemap(dir_tree: DirTree) -> Vec<SiteMapEntry> {
 
     if dir_tree.files.len() > 0 {
         for filename in dir_tree.files.keys() {
+            let local_path = format!(
+                "{}/{}",
+                dir_tree.absolute_path.trim_end_matches("/"),
+                filename
+            );
+            if content_file_path(&local_path).is_none()
+                || !local_content_is_visible(&local_path)
+                || is_language_variant(filename)
+            {
+                continue; // Not in a registered format, not published or a translation listed with its page
+            }
             // Strip leading dir in relative path
             let mut stripped_relative_path = String::new();
             if dir_tree.relative_path.ends_with("/") {
@@ -425,25 +739,70 @@ fn tree_to_sitemap(dir_tree: DirTree) -> Vec<SiteMapEntry> {
                 );
             }
             if &stripped_relative_path.len() > &0 {
-                files.push(SiteMapEntry {
+                let entry = SiteMapEntry {
                     location: format!(
                         "{}/{}/{}",
                         config.prod_host, stripped_relative_path, filename
                     ),
-                    lastmod: unix_time_to_iso(dir_tree.files[filename].modified),
+                    lastmod: stem_lastmod(&dir_tree, filename, config.git_history),
                     priority: config.xml_priority.clone(),
-                });
+                    alternates: Vec::new(),
+                };
+                files.append(&mut language_sitemap_entries(
+                    entry,
+                    &local_path,
+                    &config.prod_host,
+                ));
             } else {
-                files.push(SiteMapEntry {
+                let entry = SiteMapEntry {
                     location: format!("{}/{}", config.prod_host, filename),
-                    lastmod: unix_time_to_iso(dir_tree.files[filename].modified),
+                    lastmod: stem_lastmod(&dir_tree, filename, config.git_history),
                     priority: config.xml_priority.clone(),
-                });
+                    alternates: Vec::new(),
+                };
+                files.append(&mut language_sitemap_entries(
+                    entry,
+                    &local_path,
+                    &config.prod_host,
+                ));
             }
         }
     }
     if dir_tree.directories.len() > 0 {
         for _dir_tree in dir_tree.directories {
+            let bundle_path = PathBuf::from(_dir_tree.1.absolute_path.trim_end_matches("/"));
+            if is_page_bundle(&bundle_path) {
+                let index_path = bundle_path.join(page_bundles::BUNDLE_INDEX);
+                if local_content_is_visible(&index_path.to_string_lossy()) {
+                    let entry = SiteMapEntry {
+                        location: format!(
+                            "{}{}",
+                            config.prod_host,
+                            localpath_to_webpath(&bundle_path)
+                                .split("/")
+                                .map(|x| escape(x).to_string())
+                                .collect::<Vec<String>>()
+                                .join("/")
+                        ),
+                        lastmod: stem_lastmod(
+                            &_dir_tree.1,
+                            &page_bundles::BUNDLE_INDEX.to_string(),
+                            config.git_history,
+                        ),
+                        priority: config.xml_priority.clone(),
+                        alternates: Vec::new(),
+                    };
+                    files.append(&mut language_sitemap_entries(
+                        entry,
+                        &index_path.to_string_lossy(),
+                        &config.prod_host,
+                    ));
+                }
+                continue; // Attachments aren't pages
+            }
+            if !menu_is_visible(&add_menu_metadata(&_dir_tree.1.absolute_path)) {
+                continue;
+            }
             files.append(&mut tree_to_sitemap(_dir_tree.1));
         }
     }
@@ -451,29 +810,116 @@ fn tree_to_sitemap(dir_tree: DirTree) -> Vec<SiteMapEntry> {
     files
 }
 
+/// Last modified time of a file stem in the tree, from the git history of its content file when enabled
+fn stem_lastmod(dir_tree: &DirTree, filename: &String, use_git: bool) -> DateTime<Utc> {
+    if use_git {
+        let base = format!(
+            "{}/{}",
+            dir_tree.absolute_path.trim_end_matches("/"),
+            filename
+        );
+        if let Some(history) = content_file_path(&base).and_then(|x| git_file_history(&x)) {
+            return history.modified;
+        }
+    }
+    unix_time_to_iso(dir_tree.files[filename].modified)
+}
+
 pub fn generate_sitemap() -> Vec<SiteMapEntry> {
     let config = load_config();
     let dir_tree = file_tree::dir_to_tree(&config.local_path(), "");
 
-    let sitemap = tree_to_sitemap(dir_tree);
+    // Later listing pages aren't files in the tree either
+    let mut paginated = pagination_sitemap_entries(&dir_tree);
+    let mut sitemap = tree_to_sitemap(dir_tree);
+    // Tag and category listing pages aren't files in the tree
+    sitemap.append(&mut taxonomy_sitemap_entries(&generate_taxonomy_index()));
+    sitemap.append(&mut paginated);
 
     return sitemap;
 }
 
+/// The sitemap entries of one language, entries without language versions such as the taxonomy pages go with
+/// the default language
+///
+/// Parameters:
+///     language(&str), language code, empty for the default language
+/// Returns:
+///     Vec<SiteMapEntry>, each still listing the other language versions as alternates
+pub fn generate_sitemap_lang(language: &str) -> Vec<SiteMapEntry> {
+    let default_language = languages::default_language();
+    let language = if language.is_empty() {
+        default_language.as_str()
+    } else {
+        language
+    };
+    generate_sitemap()
+        .into_iter()
+        .filter(|entry| {
+            match entry
+                .alternates
+                .iter()
+                .find(|x| x.href == entry.location && x.hreflang != languages::X_DEFAULT)
+            {
+                Some(alternate) => alternate.hreflang == language,
+                None => language == default_language,
+            }
+        })
+        .collect()
+}
+
 pub fn generate_content_state() -> file_tree::DirTree {
     let config = load_config();
     let dir_tree = file_tree::dir_to_tree(&config.local_path(), "");
     dir_tree
 }
 
+/// Walks a DirTree and collects the web path of every piece of content in it, one entry per file stem.
+///
+/// Parameters:
+///     dir_tree(&DirTree), usually from generate_content_state()
+/// Returns:
+///     Vec<String>, sorted web paths such as "/blog/first_post"
+pub fn tree_to_webpaths(dir_tree: &DirTree) -> Vec<String> {
+    let mut webpaths: Vec<String> = Vec::new();
+    let dir_path = dir_tree.absolute_path.trim_end_matches("/");
+
+    for filename in dir_tree.files.keys() {
+        if filename.ends_with("meta") || is_language_variant(filename) {
+            continue; // Stem of a .bak copy of a metafile or a translation, served under its language prefix
+        }
+        let this_path = PathBuf::from(format!("{}/{}", dir_path, filename));
+        let webpath = localpath_to_webpath(&this_path);
+        if does_content_exist(webpath.clone()) && !webpaths.contains(&webpath) {
+            webpaths.push(webpath);
+        }
+    }
+    for sub_tree in dir_tree.directories.values() {
+        let sub_path = PathBuf::from(sub_tree.absolute_path.trim_end_matches("/"));
+        if is_page_bundle(&sub_path) {
+            let webpath = localpath_to_webpath(&sub_path);
+            if does_content_exist(webpath.clone()) && !webpaths.contains(&webpath) {
+                webpaths.push(webpath);
+            }
+            continue; // The rest of a bundle is attachments
+        }
+        webpaths.append(&mut tree_to_webpaths(sub_tree));
+    }
+
+    webpaths.sort();
+    webpaths
+}
+
 // TODO Rename this function to something clearer
 pub fn read_full_dir_sorted(web_path_dir: String) -> Vec<ContentMeta> {
+    let _read_only = read_only_content(); // Listings never write default metafiles
+    let language = current_language();
     let local_path = webpath_to_localpath(web_path_dir);
     let paths = match fs::read_dir(&local_path) {
         Err(why) => panic!("Dir exists but can't be read: {}", why),
         Ok(val) => val,
     };
-    let mut page_metas: Vec<ContentMeta> = Vec::new();
+    let mut page_metas: Vec<(String, ContentMeta)> = Vec::new();
     let mut entries_read: Vec<String> = Vec::new(); // We just need one metafile read per content file track it here
     for dir_entry in paths {
         let check_path = match &dir_entry {
@@ -481,7 +927,38 @@ pub fn read_full_dir_sorted(web_path_dir: String) -> Vec<ContentMeta> {
             Ok(val) => val.path(),
         };
         let this_path = &check_path.to_string_lossy().to_string();
-        if !&check_path.is_dir() && !this_path.ends_with("meta") {
+        // Only files in a registered format, skips metafiles and the .bak copies meta_repair leaves behind
+        let is_content = match check_path.extension() {
+            Some(extension) => is_content_extension(&extension.to_string_lossy()),
+            None => false,
+        };
+        if check_path.is_dir() {
+            // A page bundle is a page in this directory, unless a content file with its name is one already
+            let index_path = match bundle_index_path(this_path) {
+                Some(val) => localized_local_path(&val, &language),
+                None => continue,
+            };
+            let stem = check_path
+                .file_name()
+                .unwrap()
+                .to_string_lossy()
+                .to_string();
+            if !entries_read.contains(&stem) {
+                entries_read.push(stem);
+                let this_content_meta = read_content_meta(&index_path);
+                if content_is_visible(&this_content_meta) {
+                    page_metas.push((index_path, this_content_meta));
+                }
+            }
+        } else if is_content {
+            let stem = check_path
+                .file_stem()
+                .unwrap()
+                .to_string_lossy()
+                .to_string();
+            if is_language_variant(&stem) {
+                continue; // Translations are read in place of their page
+            }
             if !entries_read.iter().any(|x| {
                 // If we already read it, it's in the entries Vec so skip
                 x == &check_path
@@ -497,56 +974,188 @@ pub fn read_full_dir_sorted(web_path_dir: String) -> Vec<ContentMeta> {
                         .to_string_lossy()
                         .to_string(),
                 );
-                page_metas.push(read_content_meta(&this_path));
+                let this_path = localized_local_path(
+                    &check_path.with_extension("").to_string_lossy(),
+                    &language,
+                );
+                let this_content_meta = read_content_meta(&this_path);
+                if content_is_visible(&this_content_meta) {
+                    page_metas.push((this_path, this_content_meta));
+                }
             }
         }
     }
-    page_metas.sort_unstable_by_key(|x| x.weight);
-    page_metas
+    // The directory's own .menu_meta picks the order
+    let section_meta = add_menu_metadata(local_path.trim_end_matches("/"));
+    sort_content_metas(page_metas, &section_meta)
+}
+
+/// read_full_dir_sorted() in a language, pages without a translation are listed in the default language
+pub fn read_full_dir_sorted_lang(web_path_dir: String, language: &str) -> Vec<ContentMeta> {
+    let _language = enter_language(language);
+    read_full_dir_sorted(web_path_dir)
+}
+
+/// Paginated version of read_full_dir_sorted(), the page size comes from the .menu_meta of the directory
+///
+/// Parameters:
+///     web_path_dir(String), web path of the directory
+///     page(u32), 1 based page number
+/// Returns:
+///     Paginated<ContentMeta>, the metas for the page with the total count and neighbor page paths
+pub fn read_full_dir_paginated(web_path_dir: String, page: u32) -> Paginated<ContentMeta> {
+    let local_path = webpath_to_localpath(web_path_dir.clone());
+    let section_meta = add_menu_metadata(local_path.trim_end_matches("/"));
+    let page_metas = read_full_dir_sorted(web_path_dir.clone());
+    paginate(page_metas, page, section_meta.page_size, &web_path_dir)
 }
 
 // Mainly for reading the content_meta content_list values prefixes local dir and document base dir
-pub fn read_content_list(list_o_content: &Vec<String>) -> Vec<PageContent> {
+// Globs and queries in the list are expanded first, see the content_list module
+pub fn read_content_list(list_o_content: &[String]) -> Vec<PageContent> {
+    let _read_only = read_only_content(); // Listings never write default metafiles
+    let (webpaths, keep_order) = expand_content_list(list_o_content);
     let mut page_list: Vec<PageContent> = Vec::new();
-    for item in list_o_content {
+    for item in webpaths {
         if does_content_exist(item.clone()) {
-            page_list.push(read_single_page(item.clone()));
+            let page = read_single_page(item.clone());
+            if content_is_visible(&page.meta) {
+                page_list.push(page);
+            }
         } else {
             println!("Content list failure.  This doesn't exist: {}", item);
         }
     }
 
-    page_list.sort_unstable_by_key(|x| x.meta.weight);
+    if !keep_order {
+        page_list.sort_by(|a, b| content_tiebreak(&a.meta, &b.meta));
+    }
     page_list
 }
 
+/// Paginated version of read_content_list(), only the pages on the requested page are fully read
+///
+/// Parameters:
+///     list_o_content(&[String]), entries from a content_list
+///     page(u32), 1 based page number
+///     page_size(u32), usually the page_size of the section meta of the page holding the list
+///     base_webpath(&str), web path of the page holding the list
+/// Returns:
+///     Paginated<PageContent>, the pages for the page with the total count and neighbor page paths
+pub fn read_content_list_paginated(
+    list_o_content: &[String],
+    page: u32,
+    page_size: u32,
+    base_webpath: &str,
+) -> Paginated<PageContent> {
+    let _loading = enter_page(base_webpath); // Keep the page holding the list out of it
+    let _read_only = read_only_content(); // Listings never write default metafiles
+    paginate(
+        content_list_entries(list_o_content),
+        page,
+        page_size,
+        base_webpath,
+    )
+    .map(|(webpath, _)| read_single_page(webpath))
+}
+
+/// The existing, published entries of a content_list with their metas in the order read_content_list() uses
+pub fn content_list_entries(list_o_content: &[String]) -> Vec<(String, ContentMeta)> {
+    let _read_only = read_only_content(); // Listings never write default metafiles
+    let (webpaths, keep_order) = expand_content_list(list_o_content);
+    let mut entries: Vec<(String, ContentMeta)> = Vec::new();
+    for item in webpaths {
+        if does_content_exist(item.clone()) {
+            let local_path = localized_local_path(&content_local_path(&item), &current_language());
+            let meta = read_content_meta(&local_path);
+            if content_is_visible(&meta) {
+                entries.push((item, meta));
+            }
+        } else {
+            println!("Content list failure.  This doesn't exist: {}", item);
+        }
+    }
+
+    if !keep_order {
+        entries.sort_by(|a, b| content_tiebreak(&a.1, &b.1));
+    }
+    entries
+}
+
 /// This is a compositional function to pull the parts together into a page.  Each component load also breaks down
 /// further into file system operations, parsing and such.
 ///
 /// Parameters:
 ///     this_path(String), a web path most likely delivered by the web server routing
 /// Returns:
-///     PageContent, struct containing all the pieces of a content page
+///     PageContent, struct containing all the pieces of a content page, in the language of the page being
+///     loaded when called while loading another page, otherwise the default language
 pub fn read_single_page(this_path: String) -> PageContent {
-    let config = load_config();
+    read_single_page_lang(this_path, &current_language())
+}
 
-    let full_path_string = format!("{}{}", config.local_path(), &this_path);
+/// read_single_page() in a language, see languages
+///
+/// Parameters:
+///     this_path(String), a web path without the language prefix, see languages::split_language_prefix()
+///     language(&str), language code, empty for the default language
+/// Returns:
+///     PageContent, the translation of the page or the default language page when there isn't one
+pub fn read_single_page_lang(this_path: String, language: &str) -> PageContent {
+    let _language = enter_language(language); // Content lists and includes further down use it too
+    let language = current_language();
+    let untranslated_path = content_local_path(&this_path);
+    let full_path_string = localized_local_path(&untranslated_path, &language);
     let mut page_content: PageContent = PageContent::default();
+    let _loading = enter_page(&this_path); // Content lists further down skip this page
+
+    // SET LANGUAGE AND TRANSLATIONS
+    page_content.language = language;
+    page_content.translations = page_translations(&untranslated_path, &this_path);
 
     // SET SECTION META
-    page_content.section_meta = read_section_meta(&full_path_string);
+    page_content.section_meta = read_section_meta(&this_path);
     // SET CONTENT META
     page_content.meta = read_content_meta(&full_path_string);
+    // SET DISPLAYED DATES
+    page_content.dates = resolve_page_dates(&full_path_string, &page_content.meta);
     // SET MARKDOWN CONTENT
-    page_content.markdown = read_markdown_content(&full_path_string);
+    if uses_core_reader("md") {
+        page_content.markdown = read_markdown_content_with_options(
+            &full_path_string,
+            page_content.section_meta.sanitize.markdown_raw_html,
+        );
+    }
     // SET HTML CONTENT
-    page_content.html = read_html_content(&full_path_string);
+    if uses_core_reader("html") {
+        page_content.html = read_html_content(&full_path_string);
+    }
     // SET JSON CONTENT
-    page_content.json = read_json_content(&full_path_string);
-
-    // If the meta file contains a content_list of web paths, load the content from that list
+    if uses_core_reader("json") {
+        page_content.json = read_json_content(&full_path_string, &page_content.meta.content_type);
+    }
+    // SET CONTENT IN OTHER REGISTERED FORMATS, OR REPLACED CORE ONES
+    page_content.formatted = read_formatted_content(
+        &full_path_string,
+        &page_content.meta,
+        &page_content.section_meta,
+    );
+    // FILTER MARKDOWN AND HTML THROUGH THE SECTION SANITIZE POLICY
+    sanitize_page(&mut page_content);
+    // POINT RELATIVE REFERENCES IN A PAGE BUNDLE AT ITS ATTACHMENTS
+    if let Some(bundle_dir) = bundle_of(Path::new(&untranslated_path)) {
+        resolve_page_bundle(&mut page_content, &bundle_dir, &this_path);
+    }
+    // MERGE SECTION AND PAGE INCLUDES
+    page_content.assets = page_content
+        .meta
+        .effective_assets(&page_content.section_meta);
+    // USE FINGERPRINTED STATIC ASSETS ONCE THEY ARE BUILT
+    rewrite_page_assets(&mut page_content);
+
+    // If the meta file contains a content_list of web paths, globs or queries, load the content from that list
     // into the PageContent.list Vec.
-    // NOTE: This is recursive in an unsafe way, a circular reference will break things here
+    // NOTE: This is recursive, pages already being loaded are skipped so circular references end there
     if page_content.meta.content_list.len() > 0 {
         page_content.list = read_content_list(&page_content.meta.content_list);
     }
@@ -558,35 +1167,86 @@ pub fn read_single_page(this_path: String) -> PageContent {
 ///
 /// NOTE: Unlike the other simple readers, this one will create a default, customize it a bit and save it
 /// if the metafile doesn't exist so our page can still render somewhat correctly and we can modify the
-/// values manually.
+/// values manually.  Under read_only_content() the default is returned without saving, which is how
+/// directory listings, content lists and site wide scans read metas, so a metafile is only created
+/// when its own page is read.
 ///
 /// Parameters:
 ///     full_path_string(&String), the absolute path in the filesystem for the metafile
 /// Returns:
 ///     ContentMeta, The metafile struct for content
-pub fn read_content_meta(full_path_string: &String) -> ContentMeta {
-    let mut this_path = PathBuf::from(full_path_string);
-    this_path.set_extension("content_meta");
+pub fn read_content_meta(full_path_string: &str) -> ContentMeta {
+    let this_path = content_path_with_extension(full_path_string, "content_meta");
     if this_path.exists() {
         let this_content_meta = read_content_meta_file(this_path);
         return this_content_meta;
+    } else if let Some(untranslated) = untranslated_local_path(full_path_string) {
+        // A translation without its own meta uses the meta of the default language
+        read_content_meta(&untranslated)
     } else {
-        let mut new_meta = ContentMeta::default();
-        new_meta.title = string_from_stem(&this_path);
-        new_meta.path = localpath_to_webpath(&this_path);
-        save_content_meta_file(&this_path, &new_meta);
+        let new_meta = default_content_meta(&this_path);
+        if !is_read_only() {
+            save_content_meta_file(&this_path, &new_meta);
+        }
         return new_meta;
     }
 }
 
-pub fn read_markdown_content(this_path_string: &String) -> MDContent {
-    let mut markdown_path = PathBuf::from(this_path_string);
-    markdown_path.set_extension("md");
+thread_local! {
+    // Nesting depth of read_only_content() guards on this thread
+    static READ_ONLY: Cell<usize> = Cell::default();
+}
+
+/// Keeps read_content_meta() from writing default metafiles until the guard is dropped
+pub struct ReadOnlyGuard;
+
+impl Drop for ReadOnlyGuard {
+    fn drop(&mut self) {
+        READ_ONLY.with(|x| x.set(x.get() - 1));
+    }
+}
+
+/// Read content without touching the content tree, for scans such as the link checker or listing builds
+pub fn read_only_content() -> ReadOnlyGuard {
+    READ_ONLY.with(|x| x.set(x.get() + 1));
+    ReadOnlyGuard
+}
+
+/// Is content being read under a read_only_content() guard
+pub fn is_read_only() -> bool {
+    READ_ONLY.with(|x| x.get() > 0)
+}
+
+/// The default meta with the title and path filled in from the file name
+fn default_content_meta(this_path: &PathBuf) -> ContentMeta {
+    let mut new_meta = ContentMeta::default();
+    new_meta.title = string_from_stem(this_path);
+    new_meta.path = localpath_to_webpath(this_path);
+    // A page bundle is named after its directory, not its index file
+    if let Some(bundle_dir) = bundle_of(this_path) {
+        new_meta.title = string_from_stem(&bundle_dir);
+        new_meta.path = localpath_to_webpath(&bundle_dir);
+    }
+    new_meta
+}
+
+pub fn read_markdown_content(this_path_string: &str) -> MDContent {
+    read_markdown_content_with_options(this_path_string, false)
+}
+
+/// Same as read_markdown_content() but can pass raw HTML in the markdown through to the rendered body instead
+/// of omitting it.  Only allow raw HTML when the result is going through sanitize::sanitize_html() afterwards
+/// or the section is fully trusted.
+pub fn read_markdown_content_with_options(
+    this_path_string: &str,
+    allow_raw_html: bool,
+) -> MDContent {
+    let markdown_path = content_path_with_extension(this_path_string, "md");
     if markdown_path.exists() {
         let markdown_content = MDContent {
             created: read_file_creation_time(&markdown_path),
             modified: read_file_modified_time(&markdown_path),
-            body: read_markdown_from_path(&markdown_path), //TODO Lint/Validate/Filter here?
+            body: render_markdown(&read_markdown_source(&markdown_path), allow_raw_html),
         };
         return markdown_content;
     } else {
@@ -599,14 +1259,13 @@ pub fn read_markdown_content(this_path_string: &String) -> MDContent {
     }
 }
 
-fn read_html_content(this_path_string: &String) -> Option<HTMLContent> {
-    let mut html_path = PathBuf::from(this_path_string);
-    html_path.set_extension("html");
+fn read_html_content(this_path_string: &str) -> Option<HTMLContent> {
+    let html_path = content_path_with_extension(this_path_string, "html");
     if html_path.exists() {
         let html_content = HTMLContent {
             created: read_file_creation_time(&html_path),
             modified: read_file_modified_time(&html_path),
-            body: read_html_from_path(&html_path), //TODO Lint/Validate/Filter here?
+            body: read_html_from_path(&html_path), // Filtered by sanitize_page() in read_single_page()
         };
         return Some(html_content);
     } else {
@@ -617,14 +1276,21 @@ fn read_html_content(this_path_string: &String) -> Option<HTMLContent> {
     }
 }
 
-fn read_json_content(this_path_string: &String) -> Option<JSONContent> {
-    let mut json_path = PathBuf::from(this_path_string);
-    json_path.set_extension("json");
+fn read_json_content(this_path_string: &str, content_type: &str) -> Option<JSONContent> {
+    let json_path = content_path_with_extension(this_path_string, "json");
     if json_path.exists() {
+        let (body, errors) = parse_json_content(&read_json_from_path(&json_path), content_type);
+        let rendered = if errors.len() > 0 {
+            None
+        } else {
+            render_json_content(&body, content_type)
+        };
         let json_content = JSONContent {
             created: read_file_creation_time(&json_path),
             modified: read_file_modified_time(&json_path),
-            body: read_json_from_path(&json_path), //TODO Lint/Validate/Filter here?
+            body,
+            errors,
+            rendered,
         };
         return Some(json_content);
     } else {
@@ -677,17 +1343,44 @@ pub fn read_content_meta_file(file_path: PathBuf) -> ContentMeta {
         ),
         Ok(mut _file) => _file.read_to_string(&mut content_meta),
     };
-    // Deserialize the JSON
-    let return_struct: ContentMeta = match serde_json::from_str(&content_meta) {
+    // Deserialize the JSON, salvaging what a hand edit broke, upgrade it from older schema versions, fields
+    // that fail validation fall back to defaults
+    let parsed: Option<serde_json::Value> = match serde_json::from_str(&content_meta) {
         Err(why) => {
-            // TODO This should trigger an integrity check and correct the JSON file with default values if
-            // possible while preserving existing values.
-            let mut error_meta = ContentMeta::default();
-            error_meta.description = format!("JSON Parse Error: {}", why);
-            error_meta.title = "Error parsing metadata file".to_string();
-            error_meta
+            // TODO Change to logging
+            println!(
+                "Content meta {} JSON Parse Error: {}, n4 fix-meta can repair it",
+                file_path.to_string_lossy(),
+                why
+            );
+            salvage_json(&content_meta)
+        }
+        Ok(value) => Some(value),
+    };
+    let return_struct: ContentMeta = match parsed {
+        // Nothing salvageable, render with the defaults a missing meta file would get
+        None => default_content_meta(&file_path),
+        Some(value) => {
+            let value = match migrate_meta(value.clone(), MetaKind::Content) {
+                Ok((migrated, _)) => migrated,
+                Err(why) => {
+                    // TODO Change to logging
+                    println!("Content meta {} {}", file_path.to_string_lossy(), why);
+                    value // Validation strips the version it can't use
+                }
+            };
+            let (this_content_meta, errors) = tolerant_content_meta(value);
+            for error in errors {
+                // TODO Change to logging
+                println!(
+                    "Content meta {} field {} {}, using the default",
+                    file_path.to_string_lossy(),
+                    error.path,
+                    error.message
+                );
+            }
+            this_content_meta
         }
-        Ok(value) => value,
     };
     return_struct
 }
@@ -697,7 +1390,7 @@ pub fn read_section_meta(content_location: &String) -> MenuItemMeta {
     let config = load_config();
     let mut this_path = PathBuf::from(format!("{}{}", config.local_path(), content_location));
     this_path.pop();
-    this_path.set_extension("menu_meta");
+    let this_path = localized_menu_meta_path(&this_path.to_string_lossy(), &current_language());
     if this_path.exists() {
         let this_menu_meta = read_menu_meta_file(this_path);
         return this_menu_meta;
@@ -706,14 +1399,19 @@ pub fn read_section_meta(content_location: &String) -> MenuItemMeta {
     }
 }
 
+/// File system creation (birth) time, not every file system records one so fall back to the modified time.
+/// For dates that survive a git clone see git_history::file_history().
 pub fn read_file_creation_time(path: &std::path::Path) -> chrono::DateTime<chrono::Utc> {
     //NaiveDateTime {
     let metadata = fs::metadata(path).expect("Not found");
 
     let _ = match metadata.created() {
-        Err(why) => panic!("Couldn't get file metadata: {}", why),
+        Err(_) => return read_file_modified_time(path),
         Ok(_time) => {
-            let _temp_time = _time.duration_since(UNIX_EPOCH).unwrap().as_secs() as f64;
+            let _temp_time = match _time.duration_since(UNIX_EPOCH) {
+                Ok(val) => val.as_secs() as f64,
+                Err(_) => 0.0, // Before the epoch, clamp it
+            };
             return unix_time_to_iso(_temp_time); //NaiveDateTime::from_timestamp(_temp_time, 0);
         }
     };
@@ -724,9 +1422,12 @@ pub fn read_file_modified_time(path: &std::path::Path) -> chrono::DateTime<chron
     let metadata = fs::metadata(path).expect("Not found");
 
     let _ = match metadata.modified() {
-        Err(why) => panic!("Couldn't get file metadata: {}", why), // TODO Remove panic
+        Err(_) => return unix_time_to_iso(0.0), // Platform doesn't record it
         Ok(_time) => {
-            let _temp_time = _time.duration_since(UNIX_EPOCH).unwrap().as_secs() as f64;
+            let _temp_time = match _time.duration_since(UNIX_EPOCH) {
+                Ok(val) => val.as_secs() as f64,
+                Err(_) => 0.0, // Before the epoch, clamp it
+            };
             return unix_time_to_iso(_temp_time); //NaiveDateTime::from_timestamp(_temp_time, 0);
         }
     };
@@ -745,6 +1446,10 @@ fn localpath_to_webpath(this_localpath: &std::path::PathBuf) -> String {
     // offset is necessary for replace range, this is the calculation of it
     let offset = rel_path.find(&config.base_dir).unwrap() + config.base_dir.len(); // I think panic here is ok as it will break the site generally anyway to send anything incorrect
     rel_path.replace_range(..offset, "/");
+    // local_path() ends in a delimiter and web paths start with one, don't let the doubled one through
+    while rel_path.starts_with("//") {
+        rel_path.remove(0);
+    }
     return rel_path;
 }
 
@@ -754,36 +1459,87 @@ fn webpath_to_localpath(this_webpath: String) -> String {
     return this_local_path;
 }
 
+/// Extensionless local path of the content at a web path, the index file of the directory for a page bundle
+pub fn content_local_path(this_webpath: &str) -> String {
+    let local_path = webpath_to_localpath(this_webpath.to_string());
+    if let Some(val) = bundle_index_path(&local_path) {
+        return val;
+    }
+    if content_file_path(&local_path).is_none() {
+        // Links such as "/page.html" used to resolve to page.md by replacing the extension, keep them working
+        if let Some(val) = extension_replaced_local_path(&local_path) {
+            return val;
+        }
+    }
+    local_path
+}
+
+/// A local path without the extension of its file name, when that has content and the full path doesn't
+fn extension_replaced_local_path(local_path: &str) -> Option<String> {
+    let name_start = local_path.rfind('/').map(|x| x + 1).unwrap_or(0);
+    let dot = name_start + local_path[name_start..].rfind('.')?;
+    if dot == name_start {
+        return None; // A hidden file, there's no stem to fall back to
+    }
+    let stem_path = &local_path[..dot];
+    content_file_path(stem_path).map(|_| stem_path.to_string())
+}
+
+/// Maps a web path under /static/ to the file it should be served from, honoring the static_dir config value.
+pub fn static_webpath_to_localpath(this_webpath: &str) -> PathBuf {
+    let config = load_config();
+    if config.static_dir.len() > 0 {
+        let stripped = this_webpath.strip_prefix("/static").unwrap_or(this_webpath);
+        PathBuf::from(format!(
+            "{}{}",
+            config.static_dir.trim_end_matches("/"),
+            stripped
+        ))
+    } else {
+        PathBuf::from(format!(
+            "{}{}",
+            config.local_content_dir.trim_end_matches("/"),
+            this_webpath
+        ))
+    }
+}
+
 // This function looks for a given extension variant for a string of a path
 // TODO Add an input validation layer here, check for illegal escape attempts and return False if found
 // TODO TODO This is probably not even necessary anymore given the PathBuf.set_extension() method now
-pub fn check_path_alternatives(this_path: &String, extension: &str) -> bool {
-    let mut this_path = PathBuf::from(this_path);
-    this_path.set_extension(extension);
-    this_path.exists()
+pub fn check_path_alternatives(this_path: &str, extension: &str) -> bool {
+    content_path_with_extension(this_path, extension).exists()
 }
 
-/// Checks a given webpath to see if the base content exists in one of the three formats by extension
+/// Checks a given webpath to see if the base content exists in one of the registered formats by extension
 ///
 /// Parameters:
 ///     potential_content_webpath (String), should be a web renderable path
 /// Returns:
 ///     bool, does it exist?
 pub fn does_content_exist(potential_content_webpath: String) -> bool {
-    let mut this_path = PathBuf::from(webpath_to_localpath(potential_content_webpath));
-    this_path.set_extension("md");
-    if this_path.exists() {
-        return true;
-    }
-    this_path.set_extension("html");
-    if this_path.exists() {
-        return true;
-    }
-    this_path.set_extension("json");
-    if this_path.exists() {
-        return true;
+    content_file_path(&content_local_path(&potential_content_webpath)).is_some()
+}
+
+/// The first content file that exists for an extensionless local path, checked in format registry order
+pub fn content_file_path(local_path: &str) -> Option<PathBuf> {
+    for extension in content_extensions() {
+        let this_path = content_path_with_extension(local_path, &extension);
+        if this_path.exists() {
+            return Some(this_path);
+        }
     }
-    false
+    None
+}
+
+/// An extensionless local content path with an extension added.  Unlike PathBuf::set_extension() this keeps
+/// dotted stems whole, "page.es" with "md" is "page.es.md".
+pub fn content_path_with_extension(local_path: &str, extension: &str) -> PathBuf {
+    PathBuf::from(format!(
+        "{}.{}",
+        local_path.trim_end_matches("/"),
+        extension
+    ))
 }
 
 pub fn does_directory_exist(potential_content_webpath: String) -> bool {
@@ -795,17 +1551,29 @@ pub fn does_directory_exist(potential_content_webpath: String) -> bool {
 // TODO The following functions are place holders for the same but with strong validation
 
 pub fn read_markdown_from_path(path: &std::path::Path) -> String {
+    render_markdown(&read_markdown_source(path), false)
+}
+
+pub fn read_markdown_source(path: &std::path::Path) -> String {
     let mut content = String::new();
     let mut _file = match fs::File::open(&path) {
         Err(why) => panic!("Couldn't open file: {}", why),
         Ok(mut _file) => match _file.read_to_string(&mut content) {
             Err(why) => panic!("Couldn't read file: {}", why),
-            // Ok(_) => return markdown::to_html(&content),
-            Ok(_) => return markdown_to_html(&content, &ComrakOptions::default()),
+            Ok(_) => return content,
         },
     };
 }
 
+/// All markdown goes through here, raw HTML is replaced with a comment unless allow_raw_html is set.
+/// Shortcodes are rendered first and put back after comrak, see the shortcodes module.
+pub fn render_markdown(content: &str, allow_raw_html: bool) -> String {
+    let mut options = ComrakOptions::default();
+    options.render.unsafe_ = allow_raw_html;
+    let (content, rendered) = extract_shortcodes(content);
+    restore_shortcodes(&markdown_to_html(&content, &options), &rendered)
+}
+
 pub fn read_html_from_path(path: &std::path::Path) -> String {
     let mut content = String::new();
     let mut _file = match fs::File::open(&path) {
@@ -0,0 +1,140 @@
+//! N4 command line interface
+//!
+//! Thin wrapper over the library for the maintenance tasks that don't need a web server.
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::process;
+
+use n4::assets::{build_static_assets, MANIFEST_WEBPATH};
+use n4::check::{check_site, line_of};
+use n4::meta_repair::repair_all_meta_files;
+use n4::meta_schema::{meta_files_in_dir, validate_meta_file};
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+
+    match args.get(1).map(|x| x.as_str()) {
+        Some("setup") => n4::setup_config(),
+        Some("check") => check(),
+        Some("build-assets") => build_assets(),
+        Some("validate-meta") => validate_meta(),
+        Some("fix-meta") => fix_meta(args.iter().any(|x| x == "--dry-run")),
+        _ => usage(),
+    }
+}
+
+fn usage() {
+    println!(
+        "Usage: n4 <command>
+
+Commands:
+    setup            Create the default config file
+    check            Report broken links, content lists, icons and includes across the site
+    build-assets     Copy the static directory to output_dir with fingerprinted names and write a manifest
+    validate-meta    Validate every .content_meta and .menu_meta file against the published schemas
+    fix-meta         Repair malformed or incomplete meta files, keeping a .bak copy of each
+                     --dry-run  Print a diff of the repairs without writing anything"
+    );
+    process::exit(2);
+}
+
+/// Prints each broken reference as file:line and exits non-zero if anything was found
+fn check() {
+    let broken = check_site();
+    for item in &broken {
+        println!(
+            "{}:{}: broken {} -> {}",
+            item.file, item.line, item.kind, item.target
+        );
+    }
+    if !broken.is_empty() {
+        println!("{} broken references found.", broken.len());
+        process::exit(1);
+    }
+    println!("No broken references found.");
+}
+
+/// Builds the static assets and prints where each one went
+fn build_assets() {
+    let manifest = build_static_assets();
+    for (original, entry) in &manifest.assets {
+        println!("{} -> {}", original, entry.path);
+    }
+    println!(
+        "{} static assets, {} bundles, {} resized images and {} page attachments built, manifest at {}{}",
+        manifest.assets.len(),
+        manifest.bundles.len(),
+        manifest.images.len(),
+        manifest.attachments.len(),
+        n4::load_config().output_dir.trim_end_matches('/'),
+        MANIFEST_WEBPATH
+    );
+}
+
+/// Prints each schema error as file:line and exits non-zero if any meta file is invalid
+fn validate_meta() {
+    let config = n4::load_config();
+    let mut invalid = 0;
+    for meta_file in meta_files_in_dir(Path::new(&config.local_path())) {
+        let file_name = meta_file.to_string_lossy();
+        match validate_meta_file(&meta_file) {
+            Err(why) => {
+                println!("{}:0: {}", file_name, why);
+                invalid += 1;
+            }
+            Ok(errors) => {
+                let content = fs::read_to_string(&meta_file).unwrap_or_default();
+                for error in &errors {
+                    // Array items are found by the field holding the array, an index would match any number
+                    let field = error
+                        .path
+                        .rsplit('/')
+                        .find(|x| !x.is_empty() && !x.chars().all(|c| c.is_ascii_digit()))
+                        .unwrap_or("")
+                        .replace("~1", "/")
+                        .replace("~0", "~");
+                    println!(
+                        "{}:{}: {} {}",
+                        file_name,
+                        line_of(&content, &format!("\"{}\"", field)),
+                        error.path,
+                        error.message
+                    );
+                }
+                if !errors.is_empty() {
+                    invalid += 1;
+                }
+            }
+        }
+    }
+    if invalid > 0 {
+        println!("{} invalid meta files found.", invalid);
+        process::exit(1);
+    }
+    println!("All meta files are valid.");
+}
+
+/// Repairs every meta file in the content directory, or just shows the diffs with --dry-run
+fn fix_meta(dry_run: bool) {
+    let repairs = repair_all_meta_files(dry_run);
+    for repair in &repairs {
+        println!("{}", repair.file);
+        for problem in &repair.problems {
+            println!("    {}", problem);
+        }
+        match &repair.backup {
+            Some(backup) => println!("    Repaired, original saved as {}", backup),
+            None => {
+                for line in &repair.diff {
+                    println!("    {}", line);
+                }
+            }
+        }
+    }
+    if dry_run {
+        println!("{} meta files would be repaired.", repairs.len());
+    } else {
+        println!("{} meta files repaired.", repairs.len());
+    }
+}
@@ -0,0 +1,468 @@
+//! Renderers for the lighter content formats
+//!
+//! rst
+//!     reStructuredText flavored plain text: paragraphs, headings underlined with = - ~ or ^ (levels in order
+//!     of first use), "- " or "* " bullet lists, indented literal blocks, ``literal`` and `text <url>`_ links
+//! adoc
+//!     An AsciiDoc subset: "=" to "======" headings, * and - bullets, ". " numbered lists, ---- and ....
+//!     listing blocks, ''' rules, *bold*, _italic_, `code`, link:url[text] and url[text] links
+//! ipynb
+//!     Jupyter notebooks: markdown cells through render_markdown(), code cells as highlighted-ready <pre> blocks
+//!     followed by their text outputs
+//!
+//! Everything except notebook markdown cells is escaped so the output is safe before sanitizing.
+use serde_json::Value;
+
+use crate::render_markdown;
+
+/// Render reStructuredText flavored plain text to HTML
+pub fn render_plain_text(source: &str) -> String {
+    let mut html = String::new();
+    let mut underlines: Vec<char> = Vec::new();
+    let mut literal_next = false;
+
+    for block in blocks(source) {
+        let first_indented = block[0].starts_with(' ') || block[0].starts_with('\t');
+        if literal_next
+            || block
+                .iter()
+                .all(|x| x.starts_with(' ') || x.starts_with('\t'))
+        {
+            // Only ASCII indentation counts so the slice below stays on a char boundary, "\u{a0}" is content
+            let indent = block
+                .iter()
+                .map(|x| x.len() - x.trim_start_matches([' ', '\t']).len())
+                .min()
+                .unwrap_or(0);
+            let lines: Vec<&str> = block.iter().map(|x| &x[indent.min(x.len())..]).collect();
+            html.push_str(&format!("<pre>{}</pre>\n", escape_text(&lines.join("\n"))));
+            literal_next = false;
+            continue;
+        }
+
+        if block.len() == 2 && !first_indented && is_underline(block[1], block[0]) {
+            let marker = block[1].trim().chars().next().unwrap_or('=');
+            if !underlines.contains(&marker) {
+                underlines.push(marker);
+            }
+            let level = underlines.iter().position(|x| *x == marker).unwrap_or(0) + 1;
+            let level = level.min(6);
+            html.push_str(&format!(
+                "<h{}>{}</h{}>\n",
+                level,
+                rst_inline(block[0].trim()),
+                level
+            ));
+            continue;
+        }
+
+        if block
+            .iter()
+            .all(|x| x.starts_with("- ") || x.starts_with("* "))
+        {
+            html.push_str("<ul>\n");
+            for item in &block {
+                html.push_str(&format!("<li>{}</li>\n", rst_inline(item[2..].trim())));
+            }
+            html.push_str("</ul>\n");
+            continue;
+        }
+
+        let mut text = block.join(" ");
+        if text.ends_with("::") {
+            literal_next = true;
+            text.pop(); // "Example::" reads as "Example:"
+            if text.ends_with(" :") || text.trim() == ":" {
+                text = text.trim_end_matches(':').trim_end().to_string(); // Bare "::" disappears
+            }
+        }
+        if !text.trim().is_empty() {
+            html.push_str(&format!("<p>{}</p>\n", rst_inline(text.trim())));
+        }
+    }
+    html
+}
+
+fn is_underline(line: &str, title: &str) -> bool {
+    let line = line.trim_end();
+    let marker = match line.chars().next() {
+        Some(val) if "=-~^".contains(val) => val,
+        _ => return false,
+    };
+    line.chars().all(|x| x == marker) && line.chars().count() >= title.trim().chars().count()
+}
+
+/// Blank line separated blocks of lines
+fn blocks(source: &str) -> Vec<Vec<&str>> {
+    let mut blocks: Vec<Vec<&str>> = Vec::new();
+    let mut current: Vec<&str> = Vec::new();
+    for line in source.lines() {
+        if line.trim().is_empty() {
+            if !current.is_empty() {
+                blocks.push(std::mem::take(&mut current));
+            }
+        } else {
+            current.push(line);
+        }
+    }
+    if !current.is_empty() {
+        blocks.push(current);
+    }
+    blocks
+}
+
+fn rst_inline(text: &str) -> String {
+    let mut html = String::new();
+    let mut rest = text;
+    while !rest.is_empty() {
+        if let Some(stripped) = rest.strip_prefix("``") {
+            if let Some(end) = stripped.find("``") {
+                html.push_str(&format!("<code>{}</code>", escape_text(&stripped[..end])));
+                rest = &stripped[end + 2..];
+                continue;
+            }
+        }
+        if let Some(stripped) = rest.strip_prefix('`') {
+            if let Some(end) = stripped.find(">`_") {
+                let inner = &stripped[..end];
+                if let Some(open) = inner.rfind('<') {
+                    html.push_str(&format!(
+                        "<a href=\"{}\">{}</a>",
+                        escape_text(inner[open + 1..].trim()),
+                        escape_text(inner[..open].trim())
+                    ));
+                    rest = &stripped[end + 3..];
+                    continue;
+                }
+            }
+        }
+        let next = rest.chars().next().unwrap_or(' ');
+        html.push_str(&escape_text(&next.to_string()));
+        rest = &rest[next.len_utf8()..];
+    }
+    html
+}
+
+/// Render the supported AsciiDoc subset to HTML
+pub fn render_asciidoc(source: &str) -> String {
+    let mut html = String::new();
+    let mut paragraph: Vec<&str> = Vec::new();
+    let mut list: Option<&str> = None; // "ul" or "ol" while inside a list
+    let mut listing: Option<(&str, Vec<&str>)> = None; // Delimiter and lines while inside a listing block
+
+    for line in source.lines() {
+        if let Some((delimiter, lines)) = &mut listing {
+            if line.trim_end() == *delimiter {
+                html.push_str(&format!(
+                    "<pre><code>{}</code></pre>\n",
+                    escape_text(&lines.join("\n"))
+                ));
+                listing = None;
+            } else {
+                lines.push(line);
+            }
+            continue;
+        }
+
+        let trimmed = line.trim_end();
+        let heading = trimmed.chars().take_while(|x| *x == '=').count();
+        let block_start = trimmed.is_empty()
+            || trimmed == "----"
+            || trimmed == "...."
+            || trimmed == "'''"
+            || (heading > 0 && heading <= 6 && trimmed[heading..].starts_with(' '))
+            || trimmed.starts_with("* ")
+            || trimmed.starts_with("- ")
+            || trimmed.starts_with(". ");
+        if block_start {
+            flush_paragraph(&mut html, &mut paragraph);
+        }
+        let item = if trimmed.starts_with("* ") || trimmed.starts_with("- ") {
+            Some("ul")
+        } else if trimmed.starts_with(". ") {
+            Some("ol")
+        } else {
+            None
+        };
+        if list.is_some() && (block_start && item != list) {
+            html.push_str(&format!("</{}>\n", list.unwrap_or("ul")));
+            list = None;
+        }
+
+        if trimmed.is_empty() {
+            continue;
+        } else if trimmed == "----" || trimmed == "...." {
+            listing = Some((if trimmed == "----" { "----" } else { "...." }, Vec::new()));
+        } else if trimmed == "'''" {
+            html.push_str("<hr>\n");
+        } else if heading > 0 && heading <= 6 && trimmed[heading..].starts_with(' ') {
+            html.push_str(&format!(
+                "<h{}>{}</h{}>\n",
+                heading,
+                asciidoc_inline(trimmed[heading..].trim()),
+                heading
+            ));
+        } else if let Some(kind) = item {
+            if list.is_none() {
+                html.push_str(&format!("<{}>\n", kind));
+                list = Some(kind);
+            }
+            html.push_str(&format!(
+                "<li>{}</li>\n",
+                asciidoc_inline(trimmed[2..].trim())
+            ));
+        } else {
+            paragraph.push(trimmed);
+        }
+    }
+
+    flush_paragraph(&mut html, &mut paragraph);
+    if let Some(kind) = list {
+        html.push_str(&format!("</{}>\n", kind));
+    }
+    if let Some((_, lines)) = listing {
+        // Unterminated block, show what there is
+        html.push_str(&format!(
+            "<pre><code>{}</code></pre>\n",
+            escape_text(&lines.join("\n"))
+        ));
+    }
+    html
+}
+
+fn flush_paragraph(html: &mut String, paragraph: &mut Vec<&str>) {
+    if !paragraph.is_empty() {
+        html.push_str(&format!(
+            "<p>{}</p>\n",
+            asciidoc_inline(&paragraph.join(" "))
+        ));
+        paragraph.clear();
+    }
+}
+
+/// Escape then apply links, `code`, *bold* and _italic_
+fn asciidoc_inline(text: &str) -> String {
+    let escaped = escape_text(text);
+    let linked = asciidoc_links(&escaped);
+    let coded = replace_pairs(&linked, '`', "code");
+    let bold = replace_pairs(&coded, '*', "strong");
+    replace_pairs(&bold, '_', "em")
+}
+
+fn asciidoc_links(text: &str) -> String {
+    let mut html = String::new();
+    let mut rest = text;
+    loop {
+        let start = ["link:", "https://", "http://"]
+            .iter()
+            .filter_map(|x| rest.find(x).map(|index| (index, *x)))
+            .min();
+        let (index, prefix) = match start {
+            Some(val) => val,
+            None => break,
+        };
+        let after = &rest[index..];
+        let target_end = after
+            .find(|x: char| x.is_whitespace() || x == '[')
+            .unwrap_or(after.len());
+        let target = after[..target_end].trim_start_matches("link:");
+        html.push_str(&rest[..index]);
+        if after[target_end..].starts_with('[') {
+            if let Some(close) = after[target_end..].find(']') {
+                let label = &after[target_end + 1..target_end + close];
+                let label = if label.is_empty() { target } else { label };
+                html.push_str(&format!("<a href=\"{}\">{}</a>", target, label));
+                rest = &after[target_end + close + 1..];
+                continue;
+            }
+        }
+        if prefix == "link:" {
+            html.push_str(&after[..target_end]); // Not a macro without the brackets
+        } else {
+            html.push_str(&format!("<a href=\"{}\">{}</a>", target, target));
+        }
+        rest = &after[target_end..];
+    }
+    html.push_str(rest);
+    html
+}
+
+/// Constrained AsciiDoc formatting pairs, the marks have to hug a word and sit outside other words
+fn replace_pairs(text: &str, mark: char, tag: &str) -> String {
+    let chars: Vec<char> = text.chars().collect();
+    let mut html = String::new();
+    let mut index = 0;
+    while index < chars.len() {
+        let opens = chars[index] == mark
+            && (index == 0 || !chars[index - 1].is_alphanumeric())
+            && matches!(chars.get(index + 1), Some(x) if !x.is_whitespace() && *x != mark);
+        if opens {
+            let close = (index + 1..chars.len()).find(|x| {
+                chars[*x] == mark
+                    && !chars[*x - 1].is_whitespace()
+                    && !matches!(chars.get(*x + 1), Some(x) if x.is_alphanumeric())
+            });
+            if let Some(close) = close {
+                let inner: String = chars[index + 1..close].iter().collect();
+                html.push_str(&format!("<{}>{}</{}>", tag, inner, tag));
+                index = close + 1;
+                continue;
+            }
+        }
+        html.push(chars[index]);
+        index += 1;
+    }
+    html
+}
+
+/// Render a Jupyter notebook to HTML
+///
+/// Parameters:
+///     source(&str), the .ipynb JSON
+///     allow_raw_html(bool), passed on to render_markdown() for markdown cells
+/// Returns:
+///     Result<String, String>, Err if the file isn't a notebook
+pub fn render_notebook(source: &str, allow_raw_html: bool) -> Result<String, String> {
+    let notebook: Value = match serde_json::from_str(source) {
+        Err(why) => return Err(format!("Notebook JSON Parse Error: {}", why)),
+        Ok(val) => val,
+    };
+    let cells = match notebook.get("cells").and_then(|x| x.as_array()) {
+        Some(val) => val,
+        None => return Err(String::from("Notebook has no cells array")),
+    };
+    let language = notebook
+        .pointer("/metadata/kernelspec/language")
+        .or_else(|| notebook.pointer("/metadata/language_info/name"))
+        .and_then(|x| x.as_str())
+        .unwrap_or("");
+
+    let mut html = String::new();
+    for cell in cells {
+        let source = joined_text(cell.get("source"));
+        match cell.get("cell_type").and_then(|x| x.as_str()) {
+            Some("markdown") => html.push_str(&format!(
+                "<div class=\"cell cell-markdown\">{}</div>\n",
+                render_markdown(&source, allow_raw_html)
+            )),
+            Some("code") => {
+                html.push_str("<div class=\"cell cell-code\">");
+                html.push_str(&format!(
+                    "<pre><code class=\"language-{}\">{}</code></pre>",
+                    escape_text(language),
+                    escape_text(&source)
+                ));
+                for output in cell
+                    .get("outputs")
+                    .and_then(|x| x.as_array())
+                    .map(|x| x.as_slice())
+                    .unwrap_or(&[])
+                {
+                    let text = match output.get("output_type").and_then(|x| x.as_str()) {
+                        Some("stream") => joined_text(output.get("text")),
+                        Some("execute_result") | Some("display_data") => {
+                            joined_text(output.pointer("/data/text~1plain"))
+                        }
+                        Some("error") => format!(
+                            "{}: {}",
+                            output.get("ename").and_then(|x| x.as_str()).unwrap_or(""),
+                            output.get("evalue").and_then(|x| x.as_str()).unwrap_or("")
+                        ),
+                        _ => String::new(),
+                    };
+                    if !text.is_empty() {
+                        html.push_str(&format!(
+                            "<pre class=\"output\">{}</pre>",
+                            escape_text(&text)
+                        ));
+                    }
+                }
+                html.push_str("</div>\n");
+            }
+            _ => {} // Raw cells are for other tools
+        }
+    }
+    Ok(html)
+}
+
+/// Escape the characters that matter in HTML text and attribute values.  Unlike v_htmlescape this leaves "/"
+/// alone so links in the output stay readable to the checker and backlinks.
+fn escape_text(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Notebook text fields are either a string or a list of lines
+fn joined_text(value: Option<&Value>) -> String {
+    match value {
+        Some(Value::String(text)) => text.clone(),
+        Some(Value::Array(lines)) => lines.iter().filter_map(|x| x.as_str()).collect(),
+        _ => String::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_plain_text() {
+        let source = "Title\n=====\n\nSome ``code`` and `a link </blog/first>`_.\n\n- one\n- two\n\nExample::\n\n    x < 1\n";
+        assert_eq!(
+            render_plain_text(source),
+            "<h1>Title</h1>\n\
+             <p>Some <code>code</code> and <a href=\"/blog/first\">a link</a>.</p>\n\
+             <ul>\n<li>one</li>\n<li>two</li>\n</ul>\n\
+             <p>Example:</p>\n\
+             <pre>x &lt; 1</pre>\n"
+        );
+    }
+
+    #[test]
+    fn literal_indent_ignores_unicode_whitespace() {
+        assert_eq!(
+            render_plain_text(" \u{a0}x\n  y\n"),
+            "<pre>\u{a0}x\n y</pre>\n"
+        );
+        assert_eq!(
+            render_plain_text("Example::\n\n\u{a0}\u{a0}z\n"),
+            "<p>Example:</p>\n<pre>\u{a0}\u{a0}z</pre>\n"
+        );
+    }
+
+    #[test]
+    fn renders_asciidoc() {
+        let source = "== Section\n\nSome *bold* and _it_ with link:/blog/first[a link].\n\n* one\n* two\n\n----\nx < 1\n----\n";
+        assert_eq!(
+            render_asciidoc(source),
+            "<h2>Section</h2>\n\
+             <p>Some <strong>bold</strong> and <em>it</em> with <a href=\"/blog/first\">a link</a>.</p>\n\
+             <ul>\n<li>one</li>\n<li>two</li>\n</ul>\n\
+             <pre><code>x &lt; 1</code></pre>\n"
+        );
+        assert_eq!(
+            render_asciidoc("snake_case_name"),
+            "<p>snake_case_name</p>\n"
+        );
+    }
+
+    #[test]
+    fn renders_notebooks() {
+        let source = r##"{
+            "metadata": {"kernelspec": {"language": "python"}},
+            "cells": [
+                {"cell_type": "markdown", "source": ["# Notes"]},
+                {"cell_type": "code", "source": "1 < 2", "outputs": [
+                    {"output_type": "execute_result", "data": {"text/plain": ["True"]}}
+                ]}
+            ]
+        }"##;
+        let html = render_notebook(source, false).unwrap();
+        assert!(html.contains("<h1>Notes</h1>"));
+        assert!(html.contains("<code class=\"language-python\">1 &lt; 2</code>"));
+        assert!(html.contains("<pre class=\"output\">True</pre>"));
+        assert!(render_notebook("{}", false).is_err());
+    }
+}
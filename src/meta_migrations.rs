@@ -0,0 +1,172 @@
+//! Metadata schema versions and migrations
+//!
+//! Every .content_meta and .menu_meta file carries a schema_version, files written before it existed are
+//! version 1.  That is still the current version, so MIGRATIONS is empty.  When the shape of ContentMeta or
+//! MenuItemMeta changes in a way defaults can't cover (a renamed field, a changed type or default) bump
+//! CURRENT_SCHEMA_VERSION and add a step to MIGRATIONS that upgrades a file from the previous version, with a
+//! test for the step.
+//!
+//! Migrations run on read, so old files keep working, and in place through meta_repair (n4 fix-meta).
+use serde_json::{Map, Value};
+
+pub const CURRENT_SCHEMA_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MetaKind {
+    Content,
+    Menu,
+}
+
+/// A single upgrade step from version `from` to `from + 1`
+pub struct Migration {
+    pub from: u32,
+    pub description: &'static str,
+    pub content: fn(&mut Map<String, Value>),
+    pub menu: fn(&mut Map<String, Value>),
+}
+
+pub const MIGRATIONS: &[Migration] = &[];
+
+/// The schema version a meta document was written with
+///
+/// Parameters:
+///     value(&Value), the parsed JSON of a meta file
+/// Returns:
+///     Result<u32, String>, 1 if it predates versioning or the value is below 1, Err if the value doesn't fit
+///     a u32
+pub fn schema_version(value: &Value) -> Result<u32, String> {
+    let number = match value.get("schema_version") {
+        Some(Value::Number(val)) => val,
+        _ => return Ok(1), // Missing, or the wrong type which validation reports
+    };
+    let too_large = match number.as_u64() {
+        Some(version) if version > u32::MAX as u64 => true,
+        Some(version) => return Ok(version.max(1) as u32),
+        None => number.as_f64().unwrap_or(0.0) > u32::MAX as f64,
+    };
+    if too_large {
+        return Err(format!("schema_version {} is out of range", number));
+    }
+    Ok(1) // Negative or fractional
+}
+
+/// Upgrade a parsed meta document to CURRENT_SCHEMA_VERSION
+///
+/// Parameters:
+///     value(Value), the parsed JSON of a meta file
+///     kind(MetaKind), which sort of meta file it is
+/// Returns:
+///     Result<(Value, Vec<String>), String>, the upgraded document and a description of each step applied, Err
+///     if the version is out of range or there is no migration from it
+pub fn migrate_meta(value: Value, kind: MetaKind) -> Result<(Value, Vec<String>), String> {
+    migrate_with(value, kind, MIGRATIONS, CURRENT_SCHEMA_VERSION)
+}
+
+/// migrate_meta() with the steps and target version given
+fn migrate_with(
+    mut value: Value,
+    kind: MetaKind,
+    migrations: &[Migration],
+    target_version: u32,
+) -> Result<(Value, Vec<String>), String> {
+    let mut applied: Vec<String> = Vec::new();
+    let mut version = schema_version(&value)?;
+
+    let object = match value.as_object_mut() {
+        Some(val) => val,
+        None => return Ok((value, applied)), // Not something we can migrate, validation will report it
+    };
+    while version < target_version {
+        let step = match migrations.iter().find(|x| x.from == version) {
+            Some(val) => val,
+            None => return Err(format!("No meta migration from schema version {}", version)),
+        };
+        match kind {
+            MetaKind::Content => (step.content)(object),
+            MetaKind::Menu => (step.menu)(object),
+        }
+        version += 1;
+        object.insert(String::from("schema_version"), Value::from(version));
+        applied.push(format!(
+            "migrated schema version {} to {}: {}",
+            step.from, version, step.description
+        ));
+    }
+
+    Ok((value, applied))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn unversioned_files_are_version_one() {
+        assert_eq!(schema_version(&json!({"title": "Old"})), Ok(1));
+        assert_eq!(schema_version(&json!({"schema_version": 2})), Ok(2));
+    }
+
+    #[test]
+    fn out_of_range_versions() {
+        assert_eq!(schema_version(&json!({"schema_version": 0})), Ok(1));
+        assert_eq!(schema_version(&json!({"schema_version": -3})), Ok(1));
+        let (migrated, applied) = migrate_with(
+            json!({"schema_version": 0}),
+            MetaKind::Content,
+            RENAME_WRITER,
+            2,
+        )
+        .unwrap();
+        assert_eq!(migrated["schema_version"], 2);
+        assert_eq!(applied.len(), 1);
+
+        // Truncating would have made this version 0
+        assert!(schema_version(&json!({"schema_version": 4294967296u64})).is_err());
+        assert!(schema_version(&json!({"schema_version": 1e30})).is_err());
+        assert!(migrate_meta(json!({"schema_version": 4294967296u64}), MetaKind::Menu).is_err());
+    }
+
+    fn rename_author(meta: &mut Map<String, Value>) {
+        if let Some(author) = meta.remove("writer") {
+            meta.insert(String::from("author"), author);
+        }
+    }
+
+    fn untouched(_: &mut Map<String, Value>) {}
+
+    // A made up step for exercising the framework, MIGRATIONS itself is empty until a real change needs one
+    const RENAME_WRITER: &[Migration] = &[Migration {
+        from: 1,
+        description: "writer is renamed author",
+        content: rename_author,
+        menu: untouched,
+    }];
+
+    #[test]
+    fn migrations_run_in_order_and_stamp_the_version() {
+        let (migrated, applied) = migrate_with(
+            json!({"writer": "Ann"}),
+            MetaKind::Content,
+            RENAME_WRITER,
+            2,
+        )
+        .unwrap();
+        assert_eq!(migrated, json!({"author": "Ann", "schema_version": 2}));
+        assert_eq!(applied.len(), 1);
+
+        let (current, applied) =
+            migrate_with(migrated, MetaKind::Content, RENAME_WRITER, 2).unwrap();
+        assert_eq!(current["author"], "Ann");
+        assert!(applied.is_empty());
+        assert!(migrate_with(json!({}), MetaKind::Menu, RENAME_WRITER, 3).is_err());
+    }
+
+    #[test]
+    fn current_files_are_left_alone() {
+        let meta = json!({"schema_version": CURRENT_SCHEMA_VERSION, "weight": 5});
+        let (migrated, applied) = migrate_meta(meta.clone(), MetaKind::Content).unwrap();
+        assert_eq!(migrated, meta);
+        assert!(applied.is_empty());
+    }
+}
@@ -0,0 +1,313 @@
+//! Metadata integrity repair
+//!
+//! Rewrites malformed or incomplete .content_meta and .menu_meta files.  Whatever can be parsed and passes
+//! schema validation is kept, everything else is filled in from ContentMeta::default() or
+//! MenuItemMeta::default().  Files from older schema versions are migrated on the way through.  The original
+//! file is copied to a .bak file next to it before it is replaced, later repairs of the same file get
+//! numbered backups (.bak.1, .bak.2 ...) so the first original is never overwritten.
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_derive::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::meta_migrations::{migrate_meta, MetaKind};
+use crate::meta_schema::{
+    meta_files_in_dir, strip_invalid_fields, validate_content_meta, validate_menu_meta, SchemaError,
+};
+use crate::{load_config, localpath_to_webpath, string_from_stem, ContentMeta, MenuItemMeta};
+
+/// The outcome of repairing one meta file
+///
+/// file
+///     Local path of the meta file
+/// problems
+///     Parse and validation problems found in the original
+/// diff
+///     Line diff of the original against the repaired file, lines start with "-", "+" or " "
+/// backup
+///     Path of the backup copy, None on a dry run
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct MetaRepair {
+    pub file: String,
+    pub problems: Vec<String>,
+    pub diff: Vec<String>,
+    pub backup: Option<String>,
+}
+
+/// Repair every meta file in the content directory
+///
+/// Parameters:
+///     dry_run(bool), only report what would change
+/// Returns:
+///     Vec<MetaRepair>, one entry per file that needed (or would need) changes
+pub fn repair_all_meta_files(dry_run: bool) -> Vec<MetaRepair> {
+    let config = load_config();
+    let mut repairs: Vec<MetaRepair> = Vec::new();
+    for meta_file in meta_files_in_dir(Path::new(&config.local_path())) {
+        match repair_meta_file(&meta_file, dry_run) {
+            Err(why) => println!("{}: {}", meta_file.to_string_lossy(), why), // TODO Change to logging
+            Ok(Some(repair)) => repairs.push(repair),
+            Ok(None) => {}
+        }
+    }
+    repairs
+}
+
+/// Repair a single meta file, the kind of metadata is picked by extension
+///
+/// Parameters:
+///     file_path(&Path), a .content_meta or .menu_meta file
+///     dry_run(bool), only report what would change
+/// Returns:
+///     Result<Option<MetaRepair>, String>, None if the file is already complete and valid
+pub fn repair_meta_file(file_path: &Path, dry_run: bool) -> Result<Option<MetaRepair>, String> {
+    let original = match fs::read_to_string(file_path) {
+        Err(why) => return Err(format!("Couldn't read meta file: {}", why)),
+        Ok(val) => val,
+    };
+
+    let (repaired, problems) = match file_path.extension().and_then(|x| x.to_str()) {
+        Some("menu_meta") => repair_text(
+            &original,
+            MenuItemMeta::default(),
+            MetaKind::Menu,
+            validate_menu_meta,
+        ),
+        Some("content_meta") => {
+            // Same customized default read_content_meta() writes for missing files
+            let this_path = PathBuf::from(file_path);
+            let base = ContentMeta {
+                title: string_from_stem(&this_path),
+                path: localpath_to_webpath(&this_path),
+                ..ContentMeta::default()
+            };
+            repair_text(&original, base, MetaKind::Content, validate_content_meta)
+        }
+        _ => return Err(String::from("Not a .content_meta or .menu_meta file")),
+    }?;
+
+    if repaired == original {
+        return Ok(None);
+    }
+
+    let mut backup = None;
+    if !dry_run {
+        let backup_path = free_backup_path(file_path);
+        if let Err(why) = fs::copy(file_path, &backup_path) {
+            return Err(format!("Couldn't write backup {}: {}", backup_path, why));
+        }
+        if let Err(why) = fs::write(file_path, &repaired) {
+            return Err(format!("Couldn't write repaired meta file: {}", why));
+        }
+        backup = Some(backup_path);
+    }
+
+    Ok(Some(MetaRepair {
+        file: file_path.to_string_lossy().to_string(),
+        problems,
+        diff: line_diff(&original, &repaired),
+        backup,
+    }))
+}
+
+/// The first backup name that isn't taken yet, "page.content_meta.bak", then ".bak.1", ".bak.2" ...
+pub fn free_backup_path(file_path: &Path) -> String {
+    let base = format!("{}.bak", file_path.to_string_lossy());
+    let mut backup_path = base.clone();
+    let mut number = 0;
+    while Path::new(&backup_path).exists() {
+        number += 1;
+        backup_path = format!("{}.{}", base, number);
+    }
+    backup_path
+}
+
+/// Merge what can be salvaged from the text onto the base value and serialize it the way
+/// save_content_meta_file() does
+fn repair_text<T: Serialize + DeserializeOwned>(
+    original: &str,
+    base: T,
+    kind: MetaKind,
+    validator: fn(&Value) -> Vec<SchemaError>,
+) -> Result<(String, Vec<String>), String> {
+    let mut problems: Vec<String> = Vec::new();
+
+    let parsed = match serde_json::from_str::<Value>(original) {
+        Ok(val) => Some(val),
+        Err(why) => {
+            problems.push(format!("JSON Parse Error: {}", why));
+            salvage_json(original)
+        }
+    };
+
+    let mut merged = match serde_json::to_value(&base) {
+        Err(why) => return Err(format!("Serialize to json fail: {}", why)),
+        Ok(val) => val,
+    };
+    if let Some(parsed) = parsed {
+        // A version that can't be migrated is left for validation to report and strip
+        let parsed = match migrate_meta(parsed.clone(), kind) {
+            Ok((migrated, mut applied)) => {
+                problems.append(&mut applied);
+                migrated
+            }
+            Err(why) => {
+                problems.push(why);
+                parsed
+            }
+        };
+        let errors = validator(&parsed);
+        for error in &errors {
+            problems.push(format!("{} {}", error.path, error.message));
+        }
+        if let (Some(Value::Object(valid)), Some(target)) = (
+            strip_invalid_fields(parsed, &errors),
+            merged.as_object_mut(),
+        ) {
+            for (key, value) in valid {
+                target.insert(key, value);
+            }
+        }
+    }
+
+    let repaired: T = match serde_json::from_value(merged) {
+        Err(why) => return Err(format!("Merged metadata doesn't deserialize: {}", why)),
+        Ok(val) => val,
+    };
+    match serde_json::to_string_pretty(&repaired) {
+        Err(why) => Err(format!("Serialize to json fail: {}", why)),
+        Ok(val) => Ok((val, problems)),
+    }
+}
+
+/// Try the usual hand editing mistakes: trailing commas, unterminated strings and missing closing brackets
+pub fn salvage_json(text: &str) -> Option<Value> {
+    let mut fixed = String::new();
+    let mut closers: Vec<char> = Vec::new();
+    let mut in_string = false;
+    let mut escaped = false;
+    let chars: Vec<char> = text.chars().collect();
+
+    for (index, c) in chars.iter().enumerate() {
+        if in_string {
+            fixed.push(*c);
+            if escaped {
+                escaped = false;
+            } else if *c == '\\' {
+                escaped = true;
+            } else if *c == '"' {
+                in_string = false;
+            }
+            continue;
+        }
+        match c {
+            '"' => in_string = true,
+            '{' => closers.push('}'),
+            '[' => closers.push(']'),
+            '}' | ']' => {
+                closers.pop();
+            }
+            ',' => {
+                let next = chars[index + 1..].iter().find(|x| !x.is_whitespace());
+                if next.is_none() || next == Some(&'}') || next == Some(&']') {
+                    continue; // Trailing comma
+                }
+            }
+            _ => {}
+        }
+        fixed.push(*c);
+    }
+    if in_string {
+        fixed.push('"');
+    }
+    while let Some(closer) = closers.pop() {
+        fixed.push(closer);
+    }
+
+    serde_json::from_str(&fixed).ok()
+}
+
+/// Minimal longest common subsequence line diff, meta files are small enough for the quadratic table
+pub fn line_diff(original: &str, repaired: &str) -> Vec<String> {
+    let old: Vec<&str> = original.lines().collect();
+    let new: Vec<&str> = repaired.lines().collect();
+    let mut table = vec![vec![0usize; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            table[i][j] = if old[i] == new[j] {
+                table[i + 1][j + 1] + 1
+            } else {
+                table[i + 1][j].max(table[i][j + 1])
+            };
+        }
+    }
+
+    let mut diff: Vec<String> = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < old.len() || j < new.len() {
+        if i < old.len() && j < new.len() && old[i] == new[j] {
+            diff.push(format!(" {}", old[i]));
+            i += 1;
+            j += 1;
+        } else if j < new.len() && (i == old.len() || table[i][j + 1] >= table[i + 1][j]) {
+            diff.push(format!("+{}", new[j]));
+            j += 1;
+        } else {
+            diff.push(format!("-{}", old[i]));
+            i += 1;
+        }
+    }
+    diff
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn salvages_common_mistakes() {
+        let value = salvage_json("{\"title\": \"Kept\", \"content_list\": [\"/a\",],").unwrap();
+        assert_eq!(value["title"], "Kept");
+        assert_eq!(value["content_list"][0], "/a");
+        assert!(salvage_json("{\"title\": }").is_none());
+    }
+
+    #[test]
+    fn backups_never_overwrite() {
+        let dir = std::env::temp_dir().join(format!("n4-backup-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("page.content_meta");
+        assert!(free_backup_path(&file).ends_with("page.content_meta.bak"));
+        fs::write(dir.join("page.content_meta.bak"), "").unwrap();
+        assert!(free_backup_path(&file).ends_with("page.content_meta.bak.1"));
+        fs::write(dir.join("page.content_meta.bak.1"), "").unwrap();
+        assert!(free_backup_path(&file).ends_with("page.content_meta.bak.2"));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn repairs_keep_valid_values() {
+        let (repaired, problems) = repair_text(
+            "{\"weight\": 3, \"author\": 12,}",
+            MenuItemMeta::default(),
+            MetaKind::Menu,
+            validate_menu_meta,
+        )
+        .unwrap();
+        assert_eq!(problems.len(), 2); // Parse error and unknown field
+        let menu_meta: MenuItemMeta = serde_json::from_str(&repaired).unwrap();
+        assert_eq!(menu_meta.weight, 3);
+        assert_eq!(menu_meta.description, MenuItemMeta::default().description);
+    }
+
+    #[test]
+    fn diffs_lines() {
+        assert_eq!(
+            line_diff("a\nb\nc", "a\nc\nd"),
+            vec![" a", "-b", " c", "+d"]
+        );
+    }
+}
@@ -0,0 +1,352 @@
+//! JSON Schema validation of .content_meta and .menu_meta files
+//!
+//! The schemas live in the schemas/ directory of the repository so editors and other tools can use them, they
+//! are compiled in here.  The validator only implements the parts of JSON Schema those files use: type,
+//! properties, additionalProperties, items, enum, minimum and maximum.
+//!
+//! Parsing is tolerant, a field that fails validation is dropped and takes its default value instead of the
+//! whole file being thrown away.
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::de::DeserializeOwned;
+use serde_derive::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::{ContentMeta, MenuItemMeta};
+
+pub const CONTENT_META_SCHEMA: &str = include_str!("../schemas/content_meta.schema.json");
+pub const MENU_META_SCHEMA: &str = include_str!("../schemas/menu_meta.schema.json");
+
+/// A single validation failure
+///
+/// path
+///     JSON pointer to the offending value, "" for the document itself
+/// message
+///     What was wrong with it
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SchemaError {
+    pub path: String,
+    pub message: String,
+}
+
+pub fn content_meta_schema() -> Value {
+    match serde_json::from_str(CONTENT_META_SCHEMA) {
+        Err(why) => panic!("Bundled content meta schema is invalid: {}", why),
+        Ok(value) => value,
+    }
+}
+
+pub fn menu_meta_schema() -> Value {
+    match serde_json::from_str(MENU_META_SCHEMA) {
+        Err(why) => panic!("Bundled menu meta schema is invalid: {}", why),
+        Ok(value) => value,
+    }
+}
+
+pub fn validate_content_meta(value: &Value) -> Vec<SchemaError> {
+    validate(value, &content_meta_schema())
+}
+
+pub fn validate_menu_meta(value: &Value) -> Vec<SchemaError> {
+    validate(value, &menu_meta_schema())
+}
+
+/// Validate a value against a schema
+///
+/// Parameters:
+///     value(&Value), the parsed document
+///     schema(&Value), a schema using the supported subset of keywords
+/// Returns:
+///     Vec<SchemaError>, empty if the document is valid
+pub fn validate(value: &Value, schema: &Value) -> Vec<SchemaError> {
+    let mut errors: Vec<SchemaError> = Vec::new();
+    validate_at(value, schema, "", &mut errors);
+    errors
+}
+
+fn validate_at(value: &Value, schema: &Value, path: &str, errors: &mut Vec<SchemaError>) {
+    if let Some(expected) = schema.get("type") {
+        let allowed: Vec<&str> = match expected {
+            Value::String(name) => vec![name.as_str()],
+            Value::Array(names) => names.iter().filter_map(|x| x.as_str()).collect(),
+            _ => Vec::new(),
+        };
+        if !allowed.is_empty() && !allowed.iter().any(|x| type_matches(value, x)) {
+            errors.push(SchemaError {
+                path: path.to_string(),
+                message: format!(
+                    "expected {} but found {}",
+                    allowed.join(" or "),
+                    type_name(value)
+                ),
+            });
+            return; // Nothing below makes sense for the wrong type
+        }
+    }
+
+    if let Some(Value::Array(options)) = schema.get("enum") {
+        if !options.contains(value) {
+            let options: Vec<String> = options.iter().map(|x| x.to_string()).collect();
+            errors.push(SchemaError {
+                path: path.to_string(),
+                message: format!("must be one of {}", options.join(", ")),
+            });
+        }
+    }
+
+    if let Some(number) = value.as_f64() {
+        if let Some(minimum) = schema.get("minimum").and_then(|x| x.as_f64()) {
+            if number < minimum {
+                errors.push(SchemaError {
+                    path: path.to_string(),
+                    message: format!("must be at least {}", minimum),
+                });
+            }
+        }
+        if let Some(maximum) = schema.get("maximum").and_then(|x| x.as_f64()) {
+            if number > maximum {
+                errors.push(SchemaError {
+                    path: path.to_string(),
+                    message: format!("must be at most {}", maximum),
+                });
+            }
+        }
+    }
+
+    if let Value::Object(object) = value {
+        let properties = schema.get("properties").and_then(|x| x.as_object());
+        for (key, child) in object {
+            let child_path = format!("{}/{}", path, escape_pointer(key));
+            match properties.and_then(|x| x.get(key)) {
+                Some(child_schema) => validate_at(child, child_schema, &child_path, errors),
+                None => {
+                    if schema.get("additionalProperties") == Some(&Value::Bool(false)) {
+                        errors.push(SchemaError {
+                            path: child_path,
+                            message: String::from("is not a known field"),
+                        });
+                    }
+                }
+            }
+        }
+    }
+
+    if let (Value::Array(items), Some(item_schema)) = (value, schema.get("items")) {
+        for (index, item) in items.iter().enumerate() {
+            validate_at(item, item_schema, &format!("{}/{}", path, index), errors);
+        }
+    }
+}
+
+fn type_matches(value: &Value, name: &str) -> bool {
+    match name {
+        "object" => value.is_object(),
+        "array" => value.is_array(),
+        "string" => value.is_string(),
+        "boolean" => value.is_boolean(),
+        "null" => value.is_null(),
+        "number" => value.is_number(),
+        "integer" => value.is_i64() || value.is_u64(),
+        _ => true,
+    }
+}
+
+fn type_name(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "boolean",
+        Value::Number(_) => "number",
+        Value::String(_) => "string",
+        Value::Array(_) => "array",
+        Value::Object(_) => "object",
+    }
+}
+
+fn escape_pointer(key: &str) -> String {
+    key.replace("~", "~0").replace("/", "~1")
+}
+
+/// Parse a meta document keeping every valid field, invalid or unknown fields fall back to defaults
+///
+/// Parameters:
+///     value(Value), the parsed JSON of a .content_meta file
+/// Returns:
+///     (ContentMeta, Vec<SchemaError>), the metadata and what had to be dropped to get it
+pub fn tolerant_content_meta(value: Value) -> (ContentMeta, Vec<SchemaError>) {
+    let errors = validate_content_meta(&value);
+    (tolerant_from_value(value, &errors), errors)
+}
+
+/// Menu meta version of tolerant_content_meta()
+pub fn tolerant_menu_meta(value: Value) -> (MenuItemMeta, Vec<SchemaError>) {
+    let errors = validate_menu_meta(&value);
+    (tolerant_from_value(value, &errors), errors)
+}
+
+fn tolerant_from_value<T: DeserializeOwned + Default>(value: Value, errors: &[SchemaError]) -> T {
+    match strip_invalid_fields(value, errors) {
+        Some(value) => serde_json::from_value::<T>(value).unwrap_or_default(),
+        None => T::default(), // The document itself is wrong
+    }
+}
+
+/// Remove every field named by a validation error so what's left deserializes cleanly
+///
+/// Returns:
+///     Option<Value>, None if the document itself is invalid (not an object)
+pub fn strip_invalid_fields(mut value: Value, errors: &[SchemaError]) -> Option<Value> {
+    for error in errors {
+        if !remove_invalid_field(&mut value, &error.path) {
+            return None;
+        }
+    }
+    Some(value)
+}
+
+/// Removes the object field holding an invalid value, a bad array item takes the whole array with it.
+/// Returns false if the error is about the document root.
+fn remove_invalid_field(value: &mut Value, pointer: &str) -> bool {
+    let mut segments: Vec<String> = pointer
+        .split("/")
+        .skip(1)
+        .map(|x| x.replace("~1", "/").replace("~0", "~"))
+        .collect();
+
+    // Drop trailing array indexes until the last segment names a field of an object
+    loop {
+        if segments.is_empty() {
+            return false;
+        }
+        let parent = segments[..segments.len() - 1]
+            .iter()
+            .try_fold(&*value, |cursor, segment| child(cursor, segment));
+        match parent {
+            Some(val) if val.is_object() => break,
+            Some(_) => {
+                segments.pop();
+            }
+            None => return true, // Already removed along with an earlier error
+        }
+    }
+
+    let field = segments.pop().unwrap_or_default();
+    let mut target = value;
+    for segment in &segments {
+        target = match child_mut(target, segment) {
+            Some(val) => val,
+            None => return true,
+        };
+    }
+    if let Some(object) = target.as_object_mut() {
+        object.remove(&field);
+    }
+    true
+}
+
+fn child<'a>(value: &'a Value, segment: &str) -> Option<&'a Value> {
+    match value {
+        Value::Array(items) => segment.parse::<usize>().ok().and_then(|x| items.get(x)),
+        _ => value.get(segment),
+    }
+}
+
+fn child_mut<'a>(value: &'a mut Value, segment: &str) -> Option<&'a mut Value> {
+    match value {
+        Value::Array(items) => match segment.parse::<usize>() {
+            Ok(index) => items.get_mut(index),
+            Err(_) => None,
+        },
+        _ => value.get_mut(segment),
+    }
+}
+
+/// Read and validate a meta file, the schema is picked by extension
+///
+/// Returns:
+///     Result<Vec<SchemaError>, String>, Err if the file couldn't be read or isn't JSON at all
+pub fn validate_meta_file(file_path: &Path) -> Result<Vec<SchemaError>, String> {
+    let content = match fs::read_to_string(file_path) {
+        Err(why) => return Err(format!("Couldn't read meta file: {}", why)),
+        Ok(val) => val,
+    };
+    let value: Value = match serde_json::from_str(&content) {
+        Err(why) => return Err(format!("JSON Parse Error: {}", why)),
+        Ok(val) => val,
+    };
+    match file_path.extension().and_then(|x| x.to_str()) {
+        Some("menu_meta") => Ok(validate_menu_meta(&value)),
+        _ => Ok(validate_content_meta(&value)),
+    }
+}
+
+/// Recursively collect every .content_meta and .menu_meta file under a directory
+pub fn meta_files_in_dir(dir: &Path) -> Vec<PathBuf> {
+    let mut meta_files: Vec<PathBuf> = Vec::new();
+    let paths = match fs::read_dir(dir) {
+        Err(why) => panic!("Dir exists but can't be read: {}", why),
+        Ok(val) => val,
+    };
+    for dir_entry in paths {
+        let this_path = match dir_entry {
+            Err(why) => panic!("Well this was an unexpected entry in a dir: {}", why),
+            Ok(val) => val.path(),
+        };
+        if this_path.is_dir() {
+            meta_files.append(&mut meta_files_in_dir(&this_path));
+        } else {
+            match this_path.extension().and_then(|x| x.to_str()) {
+                Some("content_meta") | Some("menu_meta") => meta_files.push(this_path),
+                _ => {}
+            }
+        }
+    }
+    meta_files.sort();
+    meta_files
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn bundled_schemas_parse() {
+        assert!(content_meta_schema().is_object());
+        assert!(menu_meta_schema().is_object());
+    }
+
+    #[test]
+    fn reports_field_errors_by_pointer() {
+        let errors = validate_content_meta(&json!({
+            "title": 7,
+            "content_list": ["/a", 2],
+            "wieght": 10
+        }));
+        let paths: Vec<&str> = errors.iter().map(|x| x.path.as_str()).collect();
+        assert_eq!(paths, vec!["/content_list/1", "/title", "/wieght"]);
+    }
+
+    #[test]
+    fn tolerant_parsing_keeps_valid_fields() {
+        let (meta, errors) = tolerant_content_meta(json!({
+            "title": "Kept",
+            "weight": "heavy",
+            "content_list": ["/a", 2]
+        }));
+        assert_eq!(errors.len(), 2);
+        assert_eq!(meta.title, "Kept");
+        assert_eq!(meta.weight, ContentMeta::default().weight);
+        assert!(meta.content_list.is_empty());
+
+        let (menu, errors) =
+            tolerant_menu_meta(json!({"weight": 5, "sanitize": {"enabled": "no"}}));
+        assert_eq!(errors[0].path, "/sanitize/enabled");
+        assert_eq!(menu.weight, 5);
+        assert!(menu.sanitize.enabled);
+
+        let (meta, errors) = tolerant_content_meta(json!(["not", "an", "object"]));
+        assert_eq!(errors[0].path, "");
+        assert_eq!(meta.title, ContentMeta::default().title);
+    }
+}
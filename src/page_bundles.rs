@@ -0,0 +1,317 @@
+//! Page bundles
+//!
+//! A directory holding an index content file ("index.md", "index.html" ...) and no other content files, in it
+//! or below it, is a single page rather than a section.  Its web path is the directory's,
+//! "/blog/trip/index.md" is the page "/blog/trip", and everything else in it is an attachment of the page:
+//! images, downloads, data files.  A content file with the same stem as the directory, "/blog/trip.md", wins
+//! over the bundle.
+//!
+//! Relative src and href values in the rendered page that name an attachment are rewritten to absolute web
+//! paths, "photo.jpg" becomes "/blog/trip/photo.jpg", as is a relative content_icon.  build_static_assets()
+//! copies the attachments of every bundle to the same web paths under output_dir.
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::formats::is_content_extension;
+use crate::languages::{language_of_stem, site_languages};
+use crate::{
+    content_file_path, generate_content_state, tree_to_webpaths, webpath_to_localpath, PageContent,
+};
+
+/// File stem of the content file of a bundle
+pub const BUNDLE_INDEX: &str = "index";
+
+/// Is a directory a page bundle, it has an index content file and no other content files or sections below it
+pub fn is_page_bundle(dir: &Path) -> bool {
+    if content_file_path(&dir.join(BUNDLE_INDEX).to_string_lossy()).is_none() {
+        return false;
+    }
+    let entries = match fs::read_dir(dir) {
+        Err(_) => return false,
+        Ok(val) => val,
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if path.is_dir() {
+            if holds_content(&path) {
+                return false; // A section with subsections, "/docs/index.md" next to "/docs/v1/"
+            }
+            continue; // Attachment folders
+        }
+        let is_content = match path.extension() {
+            Some(extension) if extension == "menu_meta" => return false, // Meta of a subsection
+            Some(extension) => is_content_extension(&extension.to_string_lossy()),
+            None => false,
+        };
+        let stem = match path.file_stem() {
+            Some(val) if is_content => val.to_string_lossy().to_string(),
+            _ => continue,
+        };
+        // Translations of the index, "index.es.md", belong to the bundle page too
+        let is_index = stem == BUNDLE_INDEX
+            || (stem.starts_with(&format!("{}.", BUNDLE_INDEX))
+                && matches!(
+                    language_of_stem(&stem, &site_languages()),
+                    Some((BUNDLE_INDEX, _))
+                ));
+        if !is_index {
+            return false;
+        }
+    }
+    true
+}
+
+/// Is there a content file or a .menu_meta anywhere in or below a directory
+fn holds_content(dir: &Path) -> bool {
+    let entries = match fs::read_dir(dir) {
+        Err(_) => return false,
+        Ok(val) => val,
+    };
+    entries.flatten().any(|entry| {
+        let path = entry.path();
+        if path.is_dir() {
+            return holds_content(&path);
+        }
+        match path.extension() {
+            Some(extension) => {
+                let extension = extension.to_string_lossy();
+                extension == "menu_meta" || is_content_extension(&extension)
+            }
+            None => false,
+        }
+    })
+}
+
+/// The extensionless local path of a bundle's index file, None when the path isn't a bundle or a content file
+/// with the same stem exists
+pub fn bundle_index_path(local_path: &str) -> Option<String> {
+    let local_path = local_path.trim_end_matches('/');
+    if content_file_path(local_path).is_some() || !is_page_bundle(Path::new(local_path)) {
+        return None;
+    }
+    Some(format!("{}/{}", local_path, BUNDLE_INDEX))
+}
+
+/// The bundle directory an extensionless or meta file path belongs to, if it is the index of one
+pub fn bundle_of(path: &Path) -> Option<PathBuf> {
+    if path.file_stem()? != BUNDLE_INDEX {
+        return None;
+    }
+    let dir = path.parent()?;
+    if is_page_bundle(dir) {
+        Some(dir.to_path_buf())
+    } else {
+        None
+    }
+}
+
+/// The bundle directory of a page, None when the page isn't a bundle
+pub fn bundle_dir(webpath: &str) -> Option<PathBuf> {
+    let local_path = webpath_to_localpath(webpath.to_string());
+    bundle_index_path(&local_path).map(|_| PathBuf::from(local_path.trim_end_matches('/')))
+}
+
+/// Make relative references to attachments absolute
+///
+/// Parameters:
+///     html(&str), a rendered body
+///     dir(&Path), the bundle directory
+///     webpath(&str), web path of the bundle page
+/// Returns:
+///     String, the body with src and href values naming a file in the bundle pointed at its web path
+pub fn resolve_bundle_references(html: &str, dir: &Path, webpath: &str) -> String {
+    let mut resolved = String::new();
+    let mut rest = html;
+    loop {
+        let found = ["src=\"", "href=\"", "src='", "href='"]
+            .iter()
+            .filter_map(|pattern| rest.find(pattern).map(|x| (x, *pattern)))
+            .min();
+        let (start, pattern) = match found {
+            Some(val) => val,
+            None => break,
+        };
+        let value_start = start + pattern.len();
+        let quote = pattern.chars().last().unwrap_or('"');
+        let value_end = match rest[value_start..].find(quote) {
+            Some(val) => value_start + val,
+            None => break,
+        };
+        resolved.push_str(&rest[..value_start]);
+        resolved.push_str(&resolve_reference(
+            &rest[value_start..value_end],
+            dir,
+            webpath,
+        ));
+        rest = &rest[value_end..];
+    }
+    resolved.push_str(rest);
+    resolved
+}
+
+/// A relative reference to an attachment as an absolute web path, anything else unchanged
+pub fn resolve_reference(reference: &str, dir: &Path, webpath: &str) -> String {
+    let relative = reference.trim_start_matches("./");
+    let file_part = relative.split(['#', '?']).next().unwrap_or("");
+    let is_relative = !reference.is_empty()
+        && !reference.starts_with('/')
+        && !reference.starts_with('#')
+        && !reference.starts_with("../")
+        && !reference.contains(':');
+    if !is_relative || file_part.is_empty() || !dir.join(file_part).is_file() {
+        return reference.to_string();
+    }
+    format!("{}/{}", webpath.trim_end_matches('/'), relative)
+}
+
+/// Point the relative attachment references of a bundle page at their web paths
+pub fn resolve_page_bundle(page_content: &mut PageContent, dir: &Path, webpath: &str) {
+    let resolve = |body: &str| resolve_bundle_references(body, dir, webpath);
+    page_content.markdown.body = resolve(&page_content.markdown.body);
+    if let Some(html) = &mut page_content.html {
+        html.body = resolve(&html.body);
+    }
+    if let Some(rendered) = page_content.json.as_mut().and_then(|x| x.rendered.as_mut()) {
+        *rendered = resolve(rendered);
+    }
+    for formatted in &mut page_content.formatted {
+        formatted.body = resolve(&formatted.body);
+    }
+    page_content.meta.content_icon =
+        resolve_reference(&page_content.meta.content_icon, dir, webpath);
+}
+
+/// Attachments of a bundle relative to its directory, the index content and meta files are left out
+///
+/// Parameters:
+///     dir(&Path), the bundle directory
+///     languages(&[String]), from site_languages(), translations of the index are left out too
+/// Returns:
+///     Vec<String>, sorted relative paths such as "files/map.pdf"
+pub fn bundle_attachments(dir: &Path, languages: &[String]) -> Vec<String> {
+    let mut attachments: Vec<String> = Vec::new();
+    collect_attachments(dir, "", &mut attachments);
+    attachments.retain(|x| !is_index_file(x, languages));
+    attachments.sort();
+    attachments
+}
+
+/// Is a file of a bundle its index content or meta file, a translation of one or a backup meta_repair left of
+/// one.  Other files named index, "index.png", are attachments.
+fn is_index_file(name: &str, languages: &[String]) -> bool {
+    let extension = match name.strip_prefix(&format!("{}.", BUNDLE_INDEX)) {
+        Some(val) => val,
+        None => return false,
+    };
+    let extension = match extension.split_once('.') {
+        Some((language, rest)) if languages.iter().any(|x| x == language) => rest,
+        _ => extension,
+    };
+    let extension = extension.split(".bak").next().unwrap_or(extension);
+    is_content_extension(extension) || extension == "content_meta" || extension == "menu_meta"
+}
+
+fn collect_attachments(dir: &Path, prefix: &str, attachments: &mut Vec<String>) {
+    let entries = match fs::read_dir(dir) {
+        Err(_) => return,
+        Ok(val) => val,
+    };
+    for entry in entries.flatten() {
+        let name = entry.file_name().to_string_lossy().to_string();
+        if name.starts_with('.') {
+            continue;
+        }
+        let relative = format!("{}{}", prefix, name);
+        if entry.path().is_dir() {
+            collect_attachments(&entry.path(), &format!("{}/", relative), attachments);
+        } else {
+            attachments.push(relative);
+        }
+    }
+}
+
+/// Copy the attachments of every bundle in the content directory to their web paths under output_dir
+///
+/// Parameters:
+///     output_dir(&str), the build output directory without a trailing slash
+/// Returns:
+///     Vec<String>, web paths of the copied attachments
+pub fn copy_bundle_attachments(output_dir: &str) -> Vec<String> {
+    let mut copied: Vec<String> = Vec::new();
+    for webpath in tree_to_webpaths(&generate_content_state()) {
+        let dir = match bundle_dir(&webpath) {
+            Some(val) => val,
+            None => continue,
+        };
+        for attachment in bundle_attachments(&dir, &site_languages()) {
+            let attachment_webpath = format!("{}/{}", webpath.trim_end_matches('/'), attachment);
+            let output = PathBuf::from(format!("{}{}", output_dir, attachment_webpath));
+            if let Some(parent) = output.parent() {
+                if let Err(why) = fs::create_dir_all(parent) {
+                    panic!("Couldn't create {}: {}", parent.to_string_lossy(), why);
+                }
+            }
+            if let Err(why) = fs::copy(dir.join(&attachment), &output) {
+                panic!("Couldn't copy {}: {}", attachment_webpath, why);
+            }
+            copied.push(attachment_webpath);
+        }
+    }
+    copied
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resolves_attachment_references() {
+        let dir = std::env::temp_dir().join(format!("n4-bundle-{}", std::process::id()));
+        fs::create_dir_all(dir.join("files")).unwrap();
+        fs::write(dir.join("index.md"), "# Trip").unwrap();
+        fs::write(dir.join("photo.jpg"), "").unwrap();
+        fs::write(dir.join("files/map.pdf"), "").unwrap();
+        fs::write(dir.join("index.content_meta.bak"), "").unwrap();
+        fs::write(dir.join("index.png"), "").unwrap();
+        assert!(is_page_bundle(&dir));
+
+        let html = "<img src=\"photo.jpg\"><a href='./files/map.pdf#page=2'>Map</a>\
+                    <a href=\"other.jpg\">x</a><a href=\"/photo.jpg\">y</a><a href=\"https://x/photo.jpg\">z</a>";
+        assert_eq!(
+            resolve_bundle_references(html, &dir, "/blog/trip"),
+            "<img src=\"/blog/trip/photo.jpg\"><a href='/blog/trip/files/map.pdf#page=2'>Map</a>\
+             <a href=\"other.jpg\">x</a><a href=\"/photo.jpg\">y</a><a href=\"https://x/photo.jpg\">z</a>"
+        );
+        let languages = vec![String::from("en"), String::from("es")];
+        assert_eq!(
+            bundle_attachments(&dir, &languages),
+            ["files/map.pdf", "index.png", "photo.jpg"]
+        );
+        assert!(is_index_file("index.es.md", &languages));
+        assert!(is_index_file("index.es.content_meta", &languages));
+        assert!(!is_index_file("index.es.png", &languages));
+
+        fs::write(dir.join("notes.md"), "# Notes").unwrap();
+        assert!(!is_page_bundle(&dir));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn sections_with_subsections_are_not_bundles() {
+        let dir = std::env::temp_dir().join(format!("n4-docs-{}", std::process::id()));
+        fs::create_dir_all(dir.join("v1/images")).unwrap();
+        fs::write(dir.join("index.md"), "# Docs").unwrap();
+        fs::write(dir.join("v1/images/shot.png"), "").unwrap();
+        assert!(is_page_bundle(&dir));
+
+        fs::write(dir.join("v1/install.md"), "# Install").unwrap();
+        assert!(!is_page_bundle(&dir));
+        fs::remove_file(dir.join("v1/install.md")).unwrap();
+        fs::write(dir.join("v1/images.menu_meta"), "{}").unwrap();
+        assert!(!is_page_bundle(&dir));
+        fs::remove_file(dir.join("v1/images.menu_meta")).unwrap();
+        fs::write(dir.join("v1.menu_meta"), "{}").unwrap();
+        assert!(!is_page_bundle(&dir));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,194 @@
+//! Displayed created and modified dates of a page
+//!
+//! ContentMeta.created_time_default and modified_time_default pick where each date comes from:
+//!
+//! markdown, html, json, meta
+//!     Times of that content file or the .content_meta file, from git history when the git_history config
+//!     value is on and the file system otherwise
+//! git
+//!     First and last commit of the content file, even when the git_history config value is off
+//! explicit
+//!     ContentMeta.created_time / modified_time, RFC 3339 ("2021-02-14T10:00:00Z") or a plain "2021-02-14"
+//!
+//! If the chosen source isn't available the first content file that exists (md, html, json) is used, then the
+//! meta file, then the unix epoch.  The source that was actually used is recorded next to the date.
+
+use chrono::prelude::*;
+use serde_derive::{Deserialize, Serialize};
+
+use crate::git_history::{file_history, git_file_history};
+use crate::{content_file_path, content_path_with_extension, unix_time_to_iso, ContentMeta};
+
+/// Resolved dates of a page, where they came from and who made the change when git knows
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PageDates {
+    pub created: DateTime<Utc>,
+    pub modified: DateTime<Utc>,
+    pub created_source: String,
+    pub modified_source: String,
+    pub created_author: String,
+    pub modified_author: String,
+}
+
+impl Default for PageDates {
+    fn default() -> Self {
+        PageDates {
+            created: unix_time_to_iso(0.0),
+            modified: unix_time_to_iso(0.0),
+            created_source: String::from("none"),
+            modified_source: String::from("none"),
+            created_author: String::from(""),
+            modified_author: String::from(""),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Which {
+    Created,
+    Modified,
+}
+
+const FALLBACK_SOURCES: &[&str] = &["markdown", "html", "json", "meta"];
+
+/// Work out the created and modified dates of a page from its meta settings
+///
+/// Parameters:
+///     full_path_string(&str), the absolute path of the content without extension
+///     meta(&ContentMeta), the content meta of the page
+/// Returns:
+///     PageDates, the resolved dates
+pub fn resolve_page_dates(full_path_string: &str, meta: &ContentMeta) -> PageDates {
+    let (created, created_source, created_author) = resolve_date(
+        full_path_string,
+        meta,
+        &meta.created_time_default,
+        Which::Created,
+    );
+    let (modified, modified_source, modified_author) = resolve_date(
+        full_path_string,
+        meta,
+        &meta.modified_time_default,
+        Which::Modified,
+    );
+    PageDates {
+        created,
+        modified,
+        created_source,
+        modified_source,
+        created_author,
+        modified_author,
+    }
+}
+
+fn resolve_date(
+    full_path_string: &str,
+    meta: &ContentMeta,
+    requested: &str,
+    which: Which,
+) -> (DateTime<Utc>, String, String) {
+    if let Some((date, author)) = date_from_source(full_path_string, meta, requested, which) {
+        return (date, requested.to_string(), author);
+    }
+    for source in FALLBACK_SOURCES {
+        if let Some((date, author)) = date_from_source(full_path_string, meta, source, which) {
+            return (date, source.to_string(), author);
+        }
+    }
+    (
+        unix_time_to_iso(0.0),
+        String::from("none"),
+        String::from(""),
+    )
+}
+
+fn date_from_source(
+    full_path_string: &str,
+    meta: &ContentMeta,
+    source: &str,
+    which: Which,
+) -> Option<(DateTime<Utc>, String)> {
+    let extension = match source {
+        "markdown" => "md",
+        "html" => "html",
+        "json" => "json",
+        "meta" => "content_meta",
+        "explicit" => {
+            let date = match which {
+                Which::Created => parse_explicit_date(&meta.created_time),
+                Which::Modified => parse_explicit_date(&meta.modified_time),
+            };
+            return date.map(|x| (x, String::from("")));
+        }
+        "git" => {
+            let history = content_file_path(full_path_string).and_then(|x| git_file_history(&x))?;
+            return match which {
+                Which::Created => Some((history.created, history.created_author)),
+                Which::Modified => Some((history.modified, history.modified_author)),
+            };
+        }
+        _ => return None,
+    };
+    let this_path = content_path_with_extension(full_path_string, extension);
+    if !this_path.exists() {
+        return None;
+    }
+    let history = file_history(&this_path);
+    match which {
+        Which::Created => Some((history.created, history.created_author)),
+        Which::Modified => Some((history.modified, history.modified_author)),
+    }
+}
+
+/// Parse a date written into a meta file, RFC 3339 or a plain year-month-day taken as midnight UTC
+pub fn parse_explicit_date(text: &str) -> Option<DateTime<Utc>> {
+    let text = text.trim();
+    if text.is_empty() {
+        return None;
+    }
+    if let Ok(date) = DateTime::parse_from_rfc3339(text) {
+        return Some(date.with_timezone(&Utc));
+    }
+    NaiveDate::parse_from_str(text, "%Y-%m-%d")
+        .ok()
+        .and_then(|x| x.and_hms_opt(0, 0, 0))
+        .map(|x| Utc.from_utc_datetime(&x))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_explicit_dates() {
+        assert_eq!(
+            parse_explicit_date("2021-02-14T10:00:00+02:00").unwrap(),
+            Utc.from_utc_datetime(
+                &NaiveDate::from_ymd_opt(2021, 2, 14)
+                    .unwrap()
+                    .and_hms_opt(8, 0, 0)
+                    .unwrap()
+            )
+        );
+        assert_eq!(
+            parse_explicit_date("2021-02-14").unwrap().to_rfc3339(),
+            "2021-02-14T00:00:00+00:00"
+        );
+        assert!(parse_explicit_date("").is_none());
+        assert!(parse_explicit_date("last tuesday").is_none());
+    }
+
+    #[test]
+    fn explicit_source_falls_back_when_missing() {
+        let meta = ContentMeta {
+            created_time_default: String::from("explicit"),
+            modified_time_default: String::from("explicit"),
+            modified_time: String::from("2020-01-01"),
+            ..ContentMeta::default()
+        };
+        let dates = resolve_page_dates("/nonexistent/n4/page", &meta);
+        assert_eq!(dates.created_source, "none");
+        assert_eq!(dates.modified_source, "explicit");
+        assert_eq!(dates.modified.year(), 2020);
+    }
+}
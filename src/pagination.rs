@@ -0,0 +1,266 @@
+//! Pagination of directory listings and content lists
+//!
+//! MenuItemMeta.page_size sets how many entries a listing in that directory shows per page, 0 keeps everything
+//! on one page.  Page 1 lives at the listing's own web path and later pages at "<path>/page/N", so a directory
+//! named "page" can't be paginated.  split_page_path() maps a requested web path back to the listing and page
+//! number and paginated_webpaths() lists every extra page a static build needs to render, generate_sitemap()
+//! lists them through pagination_sitemap_entries().
+use std::path::{Path, PathBuf};
+
+use serde_derive::{Deserialize, Serialize};
+
+use file_tree::DirTree;
+
+use crate::content_list::enter_page;
+use crate::publication::{local_content_is_visible, menu_is_visible};
+use crate::{
+    add_menu_metadata, content_file_path, content_list_entries, content_local_path, load_config,
+    read_content_meta_file, read_file_modified_time, read_full_dir_sorted, read_only_content,
+    webpath_to_localpath, SiteMapEntry,
+};
+
+/// One page of a listing
+///
+/// page
+///     1 based page number
+/// total_items
+///     Number of entries across all pages
+/// prev, next
+///     Web paths of the neighboring pages, None at either end
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Paginated<T> {
+    pub items: Vec<T>,
+    pub page: u32,
+    pub page_size: u32,
+    pub total_items: u32,
+    pub total_pages: u32,
+    pub prev: Option<String>,
+    pub next: Option<String>,
+}
+
+impl<T> Paginated<T> {
+    /// Same page with every item converted, keeps the counts and paths
+    pub fn map<U, F: FnMut(T) -> U>(self, function: F) -> Paginated<U> {
+        Paginated {
+            items: self.items.into_iter().map(function).collect(),
+            page: self.page,
+            page_size: self.page_size,
+            total_items: self.total_items,
+            total_pages: self.total_pages,
+            prev: self.prev,
+            next: self.next,
+        }
+    }
+}
+
+/// Cut one page out of a sorted listing
+///
+/// Parameters:
+///     items(Vec<T>), every entry of the listing in display order
+///     page(u32), 1 based page number, 0 is treated as 1
+///     page_size(u32), entries per page, 0 puts everything on one page
+///     base_webpath(&str), web path of the first page of the listing
+/// Returns:
+///     Paginated<T>, the entries for the page, empty past the last page
+pub fn paginate<T>(items: Vec<T>, page: u32, page_size: u32, base_webpath: &str) -> Paginated<T> {
+    let page = page.max(1);
+    let total_items = items.len() as u32;
+    let total_pages = page_count(total_items, page_size).max(1);
+    let items = if page_size == 0 {
+        if page == 1 {
+            items
+        } else {
+            Vec::new()
+        }
+    } else {
+        let start = ((page - 1) as usize).saturating_mul(page_size as usize);
+        items
+            .into_iter()
+            .skip(start)
+            .take(page_size as usize)
+            .collect()
+    };
+
+    Paginated {
+        items,
+        page,
+        page_size,
+        total_items,
+        total_pages,
+        prev: if page > 1 {
+            Some(page_webpath(base_webpath, (page - 1).min(total_pages)))
+        } else {
+            None
+        },
+        next: if page < total_pages {
+            Some(page_webpath(base_webpath, page + 1))
+        } else {
+            None
+        },
+    }
+}
+
+/// Web path of a page of a listing, the first page is the listing itself
+pub fn page_webpath(base_webpath: &str, page: u32) -> String {
+    if page <= 1 {
+        return base_webpath.to_string();
+    }
+    format!("{}/page/{}", base_webpath.trim_end_matches('/'), page)
+}
+
+/// Split a requested web path into the listing web path and page number, "/blog/page/3" is ("/blog", 3) and
+/// anything without a page suffix is page 1
+pub fn split_page_path(webpath: &str) -> (String, u32) {
+    let trimmed = webpath.trim_end_matches('/');
+    if let Some(index) = trimmed.rfind("/page/") {
+        let (base, number) = (&trimmed[..index], &trimmed[index + "/page/".len()..]);
+        if let Ok(page) = number.parse::<u32>() {
+            if page > 0 {
+                let base = if base.is_empty() { "/" } else { base };
+                return (base.to_string(), page);
+            }
+        }
+    }
+    (webpath.to_string(), 1)
+}
+
+/// Every "/page/N" web path beyond the first page of the directory listings and content lists in the tree
+///
+/// Parameters:
+///     dir_tree(&DirTree), usually from generate_content_state()
+/// Returns:
+///     Vec<String>, sorted web paths such as "/blog/page/2"
+pub fn paginated_webpaths(dir_tree: &DirTree) -> Vec<String> {
+    let mut webpaths: Vec<String> = Vec::new();
+    let _read_only = read_only_content(); // Counting listings never writes default metafiles
+    collect_paginated_webpaths(dir_tree, "/", &mut webpaths);
+    webpaths.sort();
+    webpaths
+}
+
+/// Sitemap entries for the extra listing pages, lastmod is that of the directory or the page holding the list
+pub fn pagination_sitemap_entries(dir_tree: &DirTree) -> Vec<SiteMapEntry> {
+    let config = load_config();
+    paginated_webpaths(dir_tree)
+        .into_iter()
+        .map(|webpath| {
+            let (base_webpath, _) = split_page_path(&webpath);
+            let lastmod = match content_file_path(&content_local_path(&base_webpath)) {
+                Some(path) => read_file_modified_time(&path),
+                None => read_file_modified_time(Path::new(&webpath_to_localpath(base_webpath))),
+            };
+            SiteMapEntry {
+                location: format!("{}{}", config.prod_host.trim_end_matches('/'), webpath),
+                lastmod,
+                priority: config.xml_priority.clone(),
+                alternates: Vec::new(),
+            }
+        })
+        .collect()
+}
+
+fn collect_paginated_webpaths(dir_tree: &DirTree, dir_webpath: &str, webpaths: &mut Vec<String>) {
+    let dir_path = dir_tree.absolute_path.trim_end_matches('/');
+    let section_meta = add_menu_metadata(dir_path);
+    if dir_webpath != "/" && !menu_is_visible(&section_meta) {
+        return; // Unpublished sections aren't listed, neither is anything below them
+    }
+
+    if section_meta.page_size > 0 {
+        // The directory listing itself
+        let listing = read_full_dir_sorted(dir_webpath.to_string());
+        push_extra_pages(
+            webpaths,
+            dir_webpath,
+            listing.len() as u32,
+            section_meta.page_size,
+        );
+
+        // Content lists of the pages in the directory, only pages with a meta file can have one
+        for filename in dir_tree.files.keys() {
+            let meta_path = PathBuf::from(format!("{}/{}.content_meta", dir_path, filename));
+            if !meta_path.exists()
+                || !local_content_is_visible(&format!("{}/{}", dir_path, filename))
+            {
+                continue;
+            }
+            let meta = read_content_meta_file(meta_path);
+            if meta.content_list.is_empty() {
+                continue;
+            }
+            let page_webpath = format!("{}/{}", dir_webpath.trim_end_matches('/'), filename);
+            let _loading = enter_page(&page_webpath);
+            let total = content_list_entries(&meta.content_list).len() as u32;
+            push_extra_pages(webpaths, &page_webpath, total, section_meta.page_size);
+        }
+    }
+
+    for sub_tree in dir_tree.directories.values() {
+        // The tree keys directories by file stem, "v1.2" would be "v1"
+        let name = match Path::new(sub_tree.absolute_path.trim_end_matches('/')).file_name() {
+            Some(val) => val.to_string_lossy().to_string(),
+            None => continue,
+        };
+        let sub_webpath = format!("{}/{}", dir_webpath.trim_end_matches('/'), name);
+        collect_paginated_webpaths(sub_tree, &sub_webpath, webpaths);
+    }
+}
+
+fn push_extra_pages(
+    webpaths: &mut Vec<String>,
+    base_webpath: &str,
+    total_items: u32,
+    page_size: u32,
+) {
+    for page in 2..=page_count(total_items, page_size) {
+        webpaths.push(page_webpath(base_webpath, page));
+    }
+}
+
+/// Number of pages needed for a listing, 1 when paging is off
+fn page_count(total_items: u32, page_size: u32) -> u32 {
+    match total_items.checked_div(page_size) {
+        None => 1,
+        Some(full_pages) if full_pages * page_size < total_items => full_pages + 1,
+        Some(full_pages) => full_pages,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn paginates_with_neighbor_paths() {
+        let items: Vec<u32> = (1..=7).collect();
+        let first = paginate(items.clone(), 1, 3, "/blog");
+        assert_eq!(first.items, vec![1, 2, 3]);
+        assert_eq!((first.total_items, first.total_pages), (7, 3));
+        assert_eq!(first.prev, None);
+        assert_eq!(first.next, Some(String::from("/blog/page/2")));
+
+        let last = paginate(items.clone(), 3, 3, "/blog");
+        assert_eq!(last.items, vec![7]);
+        assert_eq!(last.prev, Some(String::from("/blog/page/2")));
+        assert_eq!(last.next, None);
+
+        let past = paginate(items.clone(), 9, 3, "/blog");
+        assert!(past.items.is_empty());
+        assert_eq!(past.prev, Some(String::from("/blog/page/3")));
+
+        let unpaged = paginate(items, 1, 0, "/");
+        assert_eq!((unpaged.items.len(), unpaged.total_pages), (7, 1));
+        assert_eq!(page_webpath("/", 2), "/page/2");
+    }
+
+    #[test]
+    fn splits_page_paths() {
+        assert_eq!(split_page_path("/blog/page/3"), (String::from("/blog"), 3));
+        assert_eq!(split_page_path("/page/2/"), (String::from("/"), 2));
+        assert_eq!(
+            split_page_path("/blog/page/zero"),
+            (String::from("/blog/page/zero"), 1)
+        );
+        assert_eq!(split_page_path("/blog"), (String::from("/blog"), 1));
+    }
+}
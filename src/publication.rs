@@ -0,0 +1,134 @@
+//! Drafts, scheduled publishing and expiry
+//!
+//! ContentMeta and MenuItemMeta carry a status of "draft", "published" or "archived" and optional publish_at
+//! and expire_at dates (RFC 3339 or YYYY-MM-DD, same as explicit page dates).  Only published content inside
+//! that window is listed by read_full_dir_sorted(), read_content_list(), the sitemap and the menus, site wide
+//! scans such as taxonomies and queries also leave out pages below an unpublished directory.  Archived
+//! content stays on disk and can still be read directly, it just isn't listed anymore.
+//!
+//! A date that doesn't parse keeps the content unlisted rather than leaking something scheduled early.  The
+//! preview config value lists everything so drafts can be checked locally.
+
+use chrono::prelude::*;
+
+use crate::languages::untranslated_local_path;
+use crate::page_dates::parse_explicit_date;
+use crate::{
+    add_menu_metadata, content_path_with_extension, load_config, read_content_meta_file,
+    webpath_to_localpath, ContentMeta, MenuItemMeta,
+};
+
+pub const STATUS_DRAFT: &str = "draft";
+pub const STATUS_PUBLISHED: &str = "published";
+pub const STATUS_ARCHIVED: &str = "archived";
+
+/// Is something with this status and window published at the given time
+///
+/// Parameters:
+///     status(&str), one of the STATUS_ values
+///     publish_at(&str), empty or the first moment it is published
+///     expire_at(&str), empty or the first moment it is no longer published
+///     now(DateTime<Utc>), the time to check against
+/// Returns:
+///     bool, is it published?
+pub fn is_published(status: &str, publish_at: &str, expire_at: &str, now: DateTime<Utc>) -> bool {
+    if status != STATUS_PUBLISHED {
+        return false;
+    }
+    if !publish_at.trim().is_empty() {
+        match parse_explicit_date(publish_at) {
+            Some(start) if now >= start => {}
+            _ => return false,
+        }
+    }
+    if !expire_at.trim().is_empty() {
+        match parse_explicit_date(expire_at) {
+            Some(end) if now < end => {}
+            _ => return false,
+        }
+    }
+    true
+}
+
+/// Should this content be listed right now, always true in preview mode
+pub fn content_is_visible(meta: &ContentMeta) -> bool {
+    load_config().preview
+        || is_published(&meta.status, &meta.publish_at, &meta.expire_at, Utc::now())
+}
+
+/// Should this directory be in the menus right now, always true in preview mode
+pub fn menu_is_visible(meta: &MenuItemMeta) -> bool {
+    load_config().preview
+        || is_published(&meta.status, &meta.publish_at, &meta.expire_at, Utc::now())
+}
+
+/// Are all the sections a page sits in visible, a draft or expired directory hides everything below it the way
+/// it does in the menus
+///
+/// Parameters:
+///     webpath(&str), web path of the page such as "/blog/2021/first"
+/// Returns:
+///     bool, false if the .menu_meta of any parent directory isn't published right now
+pub fn section_is_visible(webpath: &str) -> bool {
+    let segments: Vec<&str> = webpath.split('/').filter(|x| !x.is_empty()).collect();
+    let mut dir_webpath = String::new();
+    for segment in segments.iter().take(segments.len().saturating_sub(1)) {
+        dir_webpath = format!("{}/{}", dir_webpath, segment);
+        if !menu_is_visible(&add_menu_metadata(&webpath_to_localpath(
+            dir_webpath.clone(),
+        ))) {
+            return false;
+        }
+    }
+    true
+}
+
+/// Visibility of content by its extensionless local path.  Content without a .content_meta file is published,
+/// unlike read_content_meta() this never writes a default meta file.
+pub fn local_content_is_visible(local_path: &str) -> bool {
+    let meta_path = content_path_with_extension(local_path, "content_meta");
+    if !meta_path.exists() {
+        // A translation without its own meta goes with the default language page
+        return match untranslated_local_path(local_path) {
+            Some(untranslated) => local_content_is_visible(&untranslated),
+            None => true,
+        };
+    }
+    content_is_visible(&read_content_meta_file(meta_path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(text: &str) -> DateTime<Utc> {
+        parse_explicit_date(text).unwrap()
+    }
+
+    #[test]
+    fn only_published_status_is_published() {
+        let now = at("2021-06-01");
+        assert!(is_published(STATUS_PUBLISHED, "", "", now));
+        assert!(!is_published(STATUS_DRAFT, "", "", now));
+        assert!(!is_published(STATUS_ARCHIVED, "", "", now));
+    }
+
+    #[test]
+    fn publish_window_is_respected() {
+        let now = at("2021-06-01T12:00:00Z");
+        assert!(is_published(
+            STATUS_PUBLISHED,
+            "2021-06-01",
+            "2021-07-01",
+            now
+        ));
+        assert!(!is_published(STATUS_PUBLISHED, "2021-06-02", "", now));
+        assert!(!is_published(
+            STATUS_PUBLISHED,
+            "",
+            "2021-06-01T12:00:00Z",
+            now
+        ));
+        assert!(!is_published(STATUS_PUBLISHED, "next week", "", now));
+    }
+}
@@ -0,0 +1,354 @@
+//! Queries over content metadata
+//!
+//! A ContentQuery filters every published page in the content tree by content_type, author, license, tags,
+//! categories, a date range or a path prefix, then sorts with the same sort_by and sort_order values section
+//! listings use and applies an offset and limit.  Every filter left empty matches everything.
+//! parse_query_expression() reads the key=value form used by "query ..." content_list entries.
+//!
+//! Like the taxonomy index a query reads the meta file of every page, missing meta files are not created.
+use serde_derive::{Deserialize, Serialize};
+
+use crate::page_dates::{parse_explicit_date, resolve_page_dates, PageDates};
+use crate::publication::{content_is_visible, section_is_visible};
+use crate::sorting::{
+    content_sort_value, content_tiebreak, sort_entries, sortable_fields, SortValue,
+};
+use crate::taxonomy::slugify;
+use crate::{
+    content_local_path, generate_content_state, read_content_meta, read_only_content,
+    read_single_page, tree_to_webpaths, ContentMeta, PageContent,
+};
+
+/// Filters, order and limits of a query
+///
+/// tags, categories
+///     Every term listed must be on the page, compared by slug
+/// path_prefix
+///     Web path of a directory, matches the directory's pages and everything below it
+/// date_field, after, before
+///     "created" or "modified" page date must be on or after `after` and before `before`, RFC 3339 or
+///     YYYY-MM-DD
+/// sort_by, sort_order
+///     Same values as MenuItemMeta, see sorting
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct ContentQuery {
+    pub content_type: String,
+    pub author: String,
+    pub license: String,
+    pub tags: Vec<String>,
+    pub categories: Vec<String>,
+    pub path_prefix: String,
+    pub date_field: String,
+    pub after: String,
+    pub before: String,
+    pub sort_by: String,
+    pub sort_order: String,
+    pub offset: usize,
+    pub limit: Option<usize>,
+}
+
+impl Default for ContentQuery {
+    fn default() -> Self {
+        ContentQuery {
+            content_type: String::from(""),
+            author: String::from(""),
+            license: String::from(""),
+            tags: Vec::new(),
+            categories: Vec::new(),
+            path_prefix: String::from(""),
+            date_field: String::from("created"),
+            after: String::from(""),
+            before: String::from(""),
+            sort_by: String::from("weight"),
+            sort_order: String::from("ascending"),
+            offset: 0,
+            limit: None,
+        }
+    }
+}
+
+impl ContentQuery {
+    /// Does a page pass every filter of the query
+    ///
+    /// Parameters:
+    ///     webpath(&str), web path of the page
+    ///     meta(&ContentMeta), its content meta
+    ///     dates(&PageDates), its resolved dates, only used when after or before are set
+    /// Returns:
+    ///     bool, does it match?
+    pub fn matches(&self, webpath: &str, meta: &ContentMeta, dates: &PageDates) -> bool {
+        if !field_matches(&self.content_type, &meta.content_type)
+            || !field_matches(&self.author, &meta.author)
+            || !field_matches(&self.license, &meta.license)
+            || !terms_match(&self.tags, &meta.tags)
+            || !terms_match(&self.categories, &meta.categories)
+            || !path_matches(&self.path_prefix, webpath)
+        {
+            return false;
+        }
+
+        let date = match self.date_field.as_str() {
+            "modified" => dates.modified,
+            _ => dates.created,
+        };
+        if !self.after.trim().is_empty() {
+            match parse_explicit_date(&self.after) {
+                Some(after) if date >= after => {}
+                _ => return false,
+            }
+        }
+        if !self.before.trim().is_empty() {
+            match parse_explicit_date(&self.before) {
+                Some(before) if date < before => {}
+                _ => return false,
+            }
+        }
+        true
+    }
+
+    fn uses_dates(&self) -> bool {
+        !self.after.trim().is_empty() || !self.before.trim().is_empty()
+    }
+}
+
+fn field_matches(wanted: &str, value: &str) -> bool {
+    wanted.trim().is_empty() || wanted.trim().eq_ignore_ascii_case(value.trim())
+}
+
+fn terms_match(wanted: &[String], terms: &[String]) -> bool {
+    let slugs: Vec<String> = terms.iter().map(|x| slugify(x)).collect();
+    wanted.iter().all(|x| slugs.contains(&slugify(x)))
+}
+
+fn path_matches(prefix: &str, webpath: &str) -> bool {
+    let prefix = prefix.trim().trim_end_matches('/');
+    prefix.is_empty() || webpath == prefix || webpath.starts_with(&format!("{}/", prefix))
+}
+
+/// Parse a query written as space separated key=value pairs, values with spaces go in double quotes
+///
+/// Keys are type, author, license, tag, category, path, date, after, before, sort, order, limit and offset.
+/// tag and category can repeat or hold a comma separated list, for example
+/// "type=post tag=rust sort=created order=descending limit=5".
+///
+/// Returns:
+///     Result<ContentQuery, String>, Err naming the first pair that doesn't make sense
+pub fn parse_query_expression(expression: &str) -> Result<ContentQuery, String> {
+    let mut query = ContentQuery::default();
+    for token in split_tokens(expression) {
+        let (key, value) = match token.find('=') {
+            Some(index) => (&token[..index], token[index + 1..].to_string()),
+            None => return Err(format!("expected key=value but found {}", token)),
+        };
+        match key {
+            "type" | "content_type" => query.content_type = value,
+            "author" => query.author = value,
+            "license" => query.license = value,
+            "tag" | "tags" => query.tags.append(&mut split_list(&value)),
+            "category" | "categories" => query.categories.append(&mut split_list(&value)),
+            "path" | "path_prefix" => query.path_prefix = value,
+            "date" | "date_field" => match value.as_str() {
+                "created" | "modified" => query.date_field = value,
+                _ => return Err(format!("date must be created or modified, not {}", value)),
+            },
+            "after" | "before" => {
+                if parse_explicit_date(&value).is_none() {
+                    return Err(format!("{} isn't a date: {}", key, value));
+                }
+                if key == "after" {
+                    query.after = value;
+                } else {
+                    query.before = value;
+                }
+            }
+            "sort" | "sort_by" => {
+                if !sortable_fields().contains(&value) {
+                    return Err(format!(
+                        "can't sort on {}, see sorting::sortable_fields()",
+                        value
+                    ));
+                }
+                query.sort_by = value
+            }
+            "order" | "sort_order" => match value.as_str() {
+                "ascending" | "descending" => query.sort_order = value,
+                _ => {
+                    return Err(format!(
+                        "order must be ascending or descending, not {}",
+                        value
+                    ))
+                }
+            },
+            "limit" | "offset" => {
+                let number = match value.parse::<usize>() {
+                    Ok(val) => val,
+                    Err(_) => return Err(format!("{} isn't a number: {}", key, value)),
+                };
+                if key == "limit" {
+                    query.limit = Some(number);
+                } else {
+                    query.offset = number;
+                }
+            }
+            _ => return Err(format!("unknown query key {}", key)),
+        }
+    }
+    Ok(query)
+}
+
+pub(crate) fn split_tokens(expression: &str) -> Vec<String> {
+    let mut tokens: Vec<String> = Vec::new();
+    let mut current = String::new();
+    let mut quoted = false;
+    for c in expression.chars() {
+        match c {
+            '"' => quoted = !quoted,
+            c if c.is_whitespace() && !quoted => {
+                if !current.is_empty() {
+                    tokens.push(std::mem::take(&mut current));
+                }
+            }
+            c => current.push(c),
+        }
+    }
+    if !current.is_empty() {
+        tokens.push(current);
+    }
+    tokens
+}
+
+fn split_list(value: &str) -> Vec<String> {
+    value
+        .split(',')
+        .map(|x| x.trim().to_string())
+        .filter(|x| !x.is_empty())
+        .collect()
+}
+
+/// Run a query over the whole content tree
+///
+/// Parameters:
+///     query(&ContentQuery), the filters, order and limits
+/// Returns:
+///     Vec<(String, ContentMeta)>, web path and meta of each matching published page in order
+pub fn query_content(query: &ContentQuery) -> Vec<(String, ContentMeta)> {
+    let _read_only = read_only_content(); // Queries never write default metafiles
+    let mut matched: Vec<(SortValue, (String, ContentMeta))> = Vec::new();
+
+    for webpath in tree_to_webpaths(&generate_content_state()) {
+        if !path_matches(&query.path_prefix, &webpath) {
+            continue; // Cheap check before any meta is read
+        }
+        let full_path_string = content_local_path(&webpath);
+        let meta = read_content_meta(&full_path_string);
+        if !content_is_visible(&meta) || !section_is_visible(&webpath) {
+            continue;
+        }
+        let dates = if query.uses_dates() {
+            resolve_page_dates(&full_path_string, &meta)
+        } else {
+            PageDates::default()
+        };
+        if query.matches(&webpath, &meta, &dates) {
+            let value = content_sort_value(&meta, &full_path_string, &query.sort_by);
+            matched.push((value, (webpath, meta)));
+        }
+    }
+
+    let sorted = sort_entries(matched, &query.sort_order, |a, b| {
+        content_tiebreak(&a.1, &b.1).then_with(|| a.0.cmp(&b.0))
+    });
+    let limit = query.limit.unwrap_or(usize::MAX);
+    sorted.into_iter().skip(query.offset).take(limit).collect()
+}
+
+/// Metas of the pages matching a query, in order
+pub fn query_content_meta(query: &ContentQuery) -> Vec<ContentMeta> {
+    query_content(query).into_iter().map(|x| x.1).collect()
+}
+
+/// Web paths of the pages matching a query, in order
+pub fn query_webpaths(query: &ContentQuery) -> Vec<String> {
+    query_content(query).into_iter().map(|x| x.0).collect()
+}
+
+/// Fully read pages matching a query, in order
+pub fn query_pages(query: &ContentQuery) -> Vec<PageContent> {
+    query_content(query)
+        .into_iter()
+        .map(|x| read_single_page(x.0))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dated(day: u32) -> PageDates {
+        PageDates {
+            created: parse_explicit_date(&format!("2021-04-{:02}", day)).unwrap(),
+            ..PageDates::default()
+        }
+    }
+
+    #[test]
+    fn filters_on_meta_fields_and_paths() {
+        let meta = ContentMeta {
+            content_type: String::from("article"),
+            author: String::from("Ann"),
+            tags: vec![String::from("Rust Lang"), String::from("web")],
+            ..ContentMeta::default()
+        };
+        let query = ContentQuery {
+            content_type: String::from("Article"),
+            tags: vec![String::from("rust-lang")],
+            path_prefix: String::from("/blog/"),
+            ..ContentQuery::default()
+        };
+        assert!(query.matches("/blog/first", &meta, &dated(1)));
+        assert!(!query.matches("/blogroll/first", &meta, &dated(1)));
+
+        let wrong_author = ContentQuery {
+            author: String::from("Bob"),
+            ..query.clone()
+        };
+        assert!(!wrong_author.matches("/blog/first", &meta, &dated(1)));
+        let missing_tag = ContentQuery {
+            tags: vec![String::from("rust-lang"), String::from("go")],
+            ..query
+        };
+        assert!(!missing_tag.matches("/blog/first", &meta, &dated(1)));
+    }
+
+    #[test]
+    fn parses_query_expressions() {
+        let query = parse_query_expression(
+            "type=post tag=rust,web author=\"Ann Lee\" sort=created order=descending limit=5",
+        )
+        .unwrap();
+        assert_eq!(query.content_type, "post");
+        assert_eq!(query.tags, vec!["rust", "web"]);
+        assert_eq!(query.author, "Ann Lee");
+        assert_eq!(query.sort_order, "descending");
+        assert_eq!(query.limit, Some(5));
+        assert!(parse_query_expression("colour=blue").is_err());
+        assert!(parse_query_expression("sort=colour").is_err());
+        assert!(parse_query_expression("after=yesterday").is_err());
+        assert!(parse_query_expression("limit").is_err());
+    }
+
+    #[test]
+    fn filters_on_date_ranges() {
+        let query = ContentQuery {
+            after: String::from("2021-04-02"),
+            before: String::from("2021-04-05"),
+            ..ContentQuery::default()
+        };
+        let meta = ContentMeta::default();
+        assert!(!query.matches("/a", &meta, &dated(1)));
+        assert!(query.matches("/a", &meta, &dated(2)));
+        assert!(!query.matches("/a", &meta, &dated(5)));
+        assert!(!query.matches("/a", &meta, &PageDates::default()));
+    }
+}
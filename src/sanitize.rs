@@ -0,0 +1,142 @@
+//! Allowlist based HTML sanitization
+//!
+//! Content can come from less trusted contributors so the HTML of .html content files and rendered markdown
+//! is filtered through ammonia.  The policy is set per section through the `sanitize` value of the directory
+//! .menu_meta file, sections without one get SanitizePolicy::default().  The policy covers the content
+//! directly in the directory, it isn't inherited by subdirectories.
+use std::collections::HashSet;
+
+use ammonia::Builder;
+use serde_derive::{Deserialize, Serialize};
+
+use crate::PageContent;
+
+/// Per section sanitization settings
+///
+/// enabled
+///     Turns filtering on or off for the section, only turn it off for fully trusted content
+/// markdown_raw_html
+///     Pass raw HTML in markdown through comrak instead of omitting it, it is then subject to this policy
+/// allowed_tags
+///     Tags added to ammonia's default allowlist
+/// removed_tags
+///     Tags removed from ammonia's default allowlist
+/// allowed_attributes
+///     Attributes allowed on every tag on top of ammonia's defaults (lang, title), rel is always set by the
+///     sanitizer on links and is ignored here
+/// allowed_url_schemes
+///     Replaces ammonia's default URL scheme list when not empty
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct SanitizePolicy {
+    pub enabled: bool,
+    pub markdown_raw_html: bool,
+    pub allowed_tags: Vec<String>,
+    pub removed_tags: Vec<String>,
+    pub allowed_attributes: Vec<String>,
+    pub allowed_url_schemes: Vec<String>,
+}
+
+impl Default for SanitizePolicy {
+    fn default() -> Self {
+        SanitizePolicy {
+            enabled: true,
+            markdown_raw_html: false,
+            allowed_tags: Vec::new(),
+            removed_tags: Vec::new(),
+            allowed_attributes: vec![String::from("class"), String::from("id")],
+            allowed_url_schemes: Vec::new(),
+        }
+    }
+}
+
+/// Filter a chunk of HTML through the policy allowlist
+///
+/// Parameters:
+///     html(&str), rendered markdown or the body of an .html file
+///     policy(&SanitizePolicy), usually the section_meta.sanitize of the page
+/// Returns:
+///     String, the cleaned HTML, or the input untouched if the policy is disabled
+pub fn sanitize_html(html: &str, policy: &SanitizePolicy) -> String {
+    if !policy.enabled {
+        return html.to_string();
+    }
+
+    let mut builder = Builder::default();
+    // Tags ammonia strips along with their content (script, style) have to leave that list to be allowed
+    builder.rm_clean_content_tags(policy.allowed_tags.iter().map(|x| x.as_str()));
+    builder.add_tags(policy.allowed_tags.iter().map(|x| x.as_str()));
+    builder.rm_tags(policy.removed_tags.iter().map(|x| x.as_str()));
+    // ammonia sets rel itself and refuses a policy that also allows it
+    builder.add_generic_attributes(
+        policy
+            .allowed_attributes
+            .iter()
+            .map(|x| x.as_str())
+            .filter(|x| !x.eq_ignore_ascii_case("rel")),
+    );
+    if !policy.allowed_url_schemes.is_empty() {
+        let schemes: HashSet<&str> = policy
+            .allowed_url_schemes
+            .iter()
+            .map(|x| x.as_str())
+            .collect();
+        builder.url_schemes(schemes);
+    }
+
+    builder.clean(html).to_string()
+}
+
+/// Apply the page's section policy to its markdown, HTML, rendered JSON and other formatted bodies
+pub fn sanitize_page(page_content: &mut PageContent) {
+    let policy = &page_content.section_meta.sanitize;
+    page_content.markdown.body = sanitize_html(&page_content.markdown.body, policy);
+    if let Some(html) = &mut page_content.html {
+        html.body = sanitize_html(&html.body, policy);
+    }
+    if let Some(rendered) = page_content.json.as_mut().and_then(|x| x.rendered.as_mut()) {
+        *rendered = sanitize_html(rendered, policy);
+    }
+    for formatted in &mut page_content.formatted {
+        formatted.body = sanitize_html(&formatted.body, policy);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn strips_scripts_and_handlers_by_default() {
+        let dirty = r#"<p class="note" onclick="evil()">Hi<script>evil()</script></p>"#;
+        assert_eq!(
+            sanitize_html(dirty, &SanitizePolicy::default()),
+            r#"<p class="note">Hi</p>"#
+        );
+    }
+
+    #[test]
+    fn policy_can_extend_and_disable() {
+        let mut policy = SanitizePolicy {
+            allowed_tags: vec![String::from("iframe")],
+            removed_tags: vec![String::from("em")],
+            ..SanitizePolicy::default()
+        };
+        assert_eq!(
+            sanitize_html("<iframe></iframe><em>x</em>", &policy),
+            "<iframe></iframe>x"
+        );
+
+        policy.allowed_attributes.push(String::from("rel"));
+        assert_eq!(
+            sanitize_html("<a href=\"/x\" rel=\"me\">x</a>", &policy),
+            "<a href=\"/x\" rel=\"noopener noreferrer\">x</a>"
+        );
+
+        policy.enabled = false;
+        assert_eq!(
+            sanitize_html("<script></script>", &policy),
+            "<script></script>"
+        );
+    }
+}
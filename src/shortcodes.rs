@@ -0,0 +1,506 @@
+//! Shortcodes in markdown
+//!
+//! A shortcode is written `{{< name arguments >}}`.  Arguments are positional values or key=value pairs,
+//! values with spaces go in double quotes.  Shortcodes are swapped for placeholders before comrak renders the
+//! markdown and the placeholders are replaced with the shortcode HTML afterwards, so the output isn't mangled
+//! or stripped as raw HTML.  The result still goes through the section sanitize policy.
+//! `{{</* name */>}}` is left in the page as the literal text `{{< name >}}`.
+//!
+//! include, transclude
+//!     `{{< include "/snippets/warning" >}}` the rendered body of another page, read with read_single_page().
+//!     `{{< transclude "/docs/install" section="Requirements" >}}` or `"/docs/install#requirements"` only
+//!     the named heading and everything under it up to the next heading of the same or a higher level,
+//!     headings match on their slug.  heading=no leaves the heading itself out.  The two names are the same
+//!     shortcode.
+//! figure
+//!     `{{< figure src="/static/a.png" alt="..." caption="..." link="..." class="..." >}}`
+//! list
+//!     `{{< list "/blog" limit=5 >}}` links to the pages of a directory in its listing order, or to the pages
+//!     of any content_list entry such as a glob or a query
+//!
+//! Pages already being loaded further up (see content_list::enter_page()) can't be included or listed so
+//! embeds can't recurse forever.  A shortcode that fails renders as an HTML comment saying why, the site
+//! checker reports them through shortcode_problem().
+//! register_shortcode() adds handlers, a registered handler replaces a built in one of the same name.
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+
+use v_htmlescape::escape;
+
+use crate::content_list::is_loading;
+use crate::query::split_tokens;
+use crate::taxonomy::slugify;
+use crate::{
+    check_path_alternatives, content_list_entries, content_local_path, does_content_exist,
+    does_directory_exist, read_full_dir_sorted, read_single_page, ContentMeta,
+};
+
+/// A parsed shortcode
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Shortcode {
+    pub name: String,
+    pub positional: Vec<String>,
+    pub named: HashMap<String, String>,
+}
+
+impl Shortcode {
+    /// A named argument, or the positional one at `position` when it isn't named
+    pub fn arg(&self, key: &str, position: usize) -> Option<&str> {
+        match self.named.get(key) {
+            Some(val) => Some(val.as_str()),
+            None => self.positional.get(position).map(|x| x.as_str()),
+        }
+    }
+}
+
+/// Handler for a shortcode, returns the HTML to put in its place or why it couldn't be rendered
+pub type ShortcodeHandler = dyn Fn(&Shortcode) -> Result<String, String> + Send + Sync;
+
+// Handlers added with register_shortcode()
+static REGISTERED: RwLock<Vec<(String, Arc<ShortcodeHandler>)>> = RwLock::new(Vec::new());
+
+/// Add a shortcode handler, replacing any handler with the same name
+pub fn register_shortcode(name: &str, handler: Box<ShortcodeHandler>) {
+    let handler: Arc<ShortcodeHandler> = Arc::from(handler);
+    let mut registered = match REGISTERED.write() {
+        Err(why) => panic!("Shortcode registry lock poisoned: {}", why),
+        Ok(val) => val,
+    };
+    registered.retain(|x| x.0 != name);
+    registered.push((name.to_string(), handler));
+}
+
+fn registered_handler(name: &str) -> Option<Arc<ShortcodeHandler>> {
+    let registered = match REGISTERED.read() {
+        Err(why) => panic!("Shortcode registry lock poisoned: {}", why),
+        Ok(val) => val,
+    };
+    registered.iter().find(|x| x.0 == name).map(|x| x.1.clone())
+}
+
+/// Parse the inside of `{{< ... >}}`
+pub fn parse_shortcode(inner: &str) -> Result<Shortcode, String> {
+    let mut tokens = split_tokens(inner).into_iter();
+    let mut shortcode = Shortcode {
+        name: match tokens.next() {
+            Some(val) => val,
+            None => return Err(String::from("shortcode without a name")),
+        },
+        ..Shortcode::default()
+    };
+    for token in tokens {
+        match token.find('=') {
+            Some(index) if index > 0 => {
+                shortcode
+                    .named
+                    .insert(token[..index].to_string(), token[index + 1..].to_string());
+            }
+            _ => shortcode.positional.push(token),
+        }
+    }
+    Ok(shortcode)
+}
+
+/// Render a shortcode with the registered handler of that name or the built in one
+///
+/// Returns:
+///     Result<String, String>, the HTML or why it couldn't be rendered
+pub fn render_shortcode(shortcode: &Shortcode) -> Result<String, String> {
+    if let Some(handler) = registered_handler(&shortcode.name) {
+        return handler(shortcode);
+    }
+    match shortcode.name.as_str() {
+        "include" | "transclude" => render_include(shortcode),
+        "figure" => render_figure(shortcode),
+        "list" => render_list(shortcode),
+        name => Err(format!("unknown shortcode {}", name)),
+    }
+}
+
+/// Every shortcode in markdown in order, escaped `{{</* */>}}` ones are skipped
+pub fn shortcodes_in(source: &str) -> Vec<Result<Shortcode, String>> {
+    let mut shortcodes: Vec<Result<Shortcode, String>> = Vec::new();
+    let mut rest = source;
+    while let Some(start) = rest.find("{{<") {
+        let end = match rest[start..].find(">}}") {
+            Some(val) => start + val,
+            None => break,
+        };
+        let inner = rest[start + 3..end].trim();
+        if !(inner.starts_with("/*") && inner.ends_with("*/")) {
+            shortcodes.push(parse_shortcode(inner));
+        }
+        rest = &rest[end + 3..];
+    }
+    shortcodes
+}
+
+/// Why a shortcode on a page won't render, for the site checker
+///
+/// Parameters:
+///     page_webpath(&str), web path of the page holding the shortcode
+///     shortcode(&Shortcode), the parsed shortcode
+/// Returns:
+///     Option<(String, String)>, the target as written and what is wrong with it, None if nothing is
+pub fn shortcode_problem(page_webpath: &str, shortcode: &Shortcode) -> Option<(String, String)> {
+    match shortcode.name.as_str() {
+        _ if registered_handler(&shortcode.name).is_some() => None,
+        "include" | "transclude" => {
+            let (target, section) = match include_target(shortcode) {
+                Ok(val) => val,
+                Err(why) => return Some((shortcode.name.clone(), why)),
+            };
+            if target == page_webpath {
+                return Some((target, String::from("includes itself")));
+            }
+            if !does_content_exist(target.clone()) {
+                return Some((target, String::from("doesn't exist")));
+            }
+            let section = section?;
+            match extract_section(&include_body(target.clone()), &section, true) {
+                Some(_) => None,
+                None => Some((
+                    format!("{}#{}", target, section),
+                    String::from("has no such section"),
+                )),
+            }
+        }
+        "figure" | "list" => None,
+        name => Some((name.to_string(), String::from("unknown shortcode"))),
+    }
+}
+
+/// Swap every shortcode in markdown for a placeholder and render it
+///
+/// Parameters:
+///     source(&str), the markdown
+/// Returns:
+///     (String, Vec<String>), the markdown with placeholders and the HTML for each placeholder in order
+pub fn extract_shortcodes(source: &str) -> (String, Vec<String>) {
+    let mut markdown = String::new();
+    let mut rendered: Vec<String> = Vec::new();
+    let mut rest = source;
+    while let Some(start) = rest.find("{{<") {
+        let end = match rest[start..].find(">}}") {
+            Some(val) => start + val,
+            None => break, // Unterminated, leave the rest as text
+        };
+        markdown.push_str(&rest[..start]);
+        let inner = rest[start + 3..end].trim();
+        if inner.starts_with("/*") && inner.ends_with("*/") && inner.len() >= 4 {
+            markdown.push_str(&format!("{{{{< {} >}}}}", inner[2..inner.len() - 2].trim()));
+        } else {
+            let html = match parse_shortcode(inner).and_then(|x| render_shortcode(&x)) {
+                Ok(val) => val,
+                Err(why) => format!("<!-- shortcode error: {} -->", escape(&why)),
+            };
+            markdown.push_str(&placeholder(rendered.len()));
+            rendered.push(html);
+        }
+        rest = &rest[end + 3..];
+    }
+    markdown.push_str(rest);
+    (markdown, rendered)
+}
+
+/// Put the shortcode HTML back in rendered markdown, a placeholder alone in a paragraph replaces the paragraph
+pub fn restore_shortcodes(html: &str, rendered: &[String]) -> String {
+    let mut html = html.to_string();
+    for (index, shortcode_html) in rendered.iter().enumerate() {
+        let marker = placeholder(index);
+        html = html
+            .replace(&format!("<p>{}</p>", marker), shortcode_html)
+            .replace(&marker, shortcode_html);
+    }
+    html
+}
+
+// Letters and digits only so markdown leaves it alone, the trailing X keeps 1 from matching 10
+fn placeholder(index: usize) -> String {
+    format!("N4SHORTCODE{}X", index)
+}
+
+/// Web path and optional section of an include, from the section argument or a "#fragment"
+fn include_target(shortcode: &Shortcode) -> Result<(String, Option<String>), String> {
+    let written = match shortcode.arg("path", 0) {
+        Some(val) => val,
+        None => return Err(format!("{} needs a web path", shortcode.name)),
+    };
+    let (target, fragment) = match written.find('#') {
+        Some(index) => (&written[..index], Some(written[index + 1..].to_string())),
+        None => (written, None),
+    };
+    let section = match shortcode.named.get("section") {
+        Some(val) => Some(val.clone()),
+        None => fragment,
+    };
+    Ok((target.to_string(), section.filter(|x| !x.trim().is_empty())))
+}
+
+fn render_include(shortcode: &Shortcode) -> Result<String, String> {
+    let (target, section) = include_target(shortcode)?;
+    if is_loading(&target) {
+        return Err(format!("include cycle at {}", target));
+    }
+    if !does_content_exist(target.clone()) {
+        return Err(format!("include target doesn't exist: {}", target));
+    }
+    let body = include_body(target.clone());
+    let section = match section {
+        Some(val) => val,
+        None => return Ok(body),
+    };
+    let keep_heading = !matches!(
+        shortcode.named.get("heading").map(|x| x.as_str()),
+        Some("no") | Some("false")
+    );
+    match extract_section(&body, &section, keep_heading) {
+        Some(val) => Ok(val),
+        None => Err(format!("{} has no section {}", target, section)),
+    }
+}
+
+/// The rendered body of a page, markdown first then html, rendered json and other formats
+fn include_body(target: String) -> String {
+    let local_path = content_local_path(&target);
+    let page = read_single_page(target);
+    if check_path_alternatives(&local_path, "md") {
+        return page.markdown.body;
+    }
+    if let Some(html) = page.html {
+        return html.body;
+    }
+    if let Some(rendered) = page.json.and_then(|x| x.rendered) {
+        return rendered;
+    }
+    match page.formatted.into_iter().next() {
+        Some(formatted) => formatted.body,
+        None => String::new(),
+    }
+}
+
+/// Cut a heading section out of rendered HTML
+///
+/// Parameters:
+///     html(&str), a rendered page body
+///     section(&str), heading text or its slug
+///     keep_heading(bool), include the heading element itself
+/// Returns:
+///     Option<String>, from the heading up to the next heading of the same or a higher level, None if no
+///     heading matches
+pub fn extract_section(html: &str, section: &str, keep_heading: bool) -> Option<String> {
+    let wanted = slugify(section);
+    let headings = headings_in(html);
+    let position = headings
+        .iter()
+        .position(|x| slugify(&strip_tags(&html[x.content_start..x.content_end])) == wanted)?;
+    let heading = &headings[position];
+    let end = headings[position + 1..]
+        .iter()
+        .find(|x| x.level <= heading.level)
+        .map(|x| x.start)
+        .unwrap_or(html.len());
+    let start = if keep_heading {
+        heading.start
+    } else {
+        heading.end
+    };
+    Some(html[start..end].trim().to_string())
+}
+
+struct Heading {
+    level: u8,
+    start: usize,         // Of the opening tag
+    content_start: usize, // After the opening tag
+    content_end: usize,   // Of the closing tag
+    end: usize,           // After the closing tag
+}
+
+fn headings_in(html: &str) -> Vec<Heading> {
+    let bytes = html.as_bytes();
+    let mut headings: Vec<Heading> = Vec::new();
+    let mut index = 0;
+    while let Some(found) = html[index..].find("<h") {
+        let start = index + found;
+        index = start + 2;
+        let level = match bytes.get(start + 2) {
+            Some(digit) if (b'1'..=b'6').contains(digit) => digit - b'0',
+            _ => continue,
+        };
+        match bytes.get(start + 3) {
+            Some(b'>') | Some(b' ') => {}
+            _ => continue, // <hr>, <header> and the like
+        }
+        let content_start = match html[start..].find('>') {
+            Some(val) => start + val + 1,
+            None => break,
+        };
+        let closing = format!("</h{}>", level);
+        let content_end = match html[content_start..].find(&closing) {
+            Some(val) => content_start + val,
+            None => continue,
+        };
+        headings.push(Heading {
+            level,
+            start,
+            content_start,
+            content_end,
+            end: content_end + closing.len(),
+        });
+        index = content_end;
+    }
+    headings
+}
+
+fn strip_tags(html: &str) -> String {
+    let mut text = String::new();
+    let mut in_tag = false;
+    for c in html.chars() {
+        match c {
+            '<' => in_tag = true,
+            '>' => in_tag = false,
+            c if !in_tag => text.push(c),
+            _ => {}
+        }
+    }
+    text
+}
+
+fn render_figure(shortcode: &Shortcode) -> Result<String, String> {
+    let src = match shortcode.arg("src", 0) {
+        Some(val) => val,
+        None => return Err(String::from("figure needs a src")),
+    };
+    let field = |name: &str| shortcode.named.get(name).map(|x| x.as_str()).unwrap_or("");
+    let mut html = if field("class").is_empty() {
+        String::from("<figure>")
+    } else {
+        format!("<figure class=\"{}\">", escape(field("class")))
+    };
+    let image = format!(
+        "<img src=\"{}\" alt=\"{}\">",
+        escape(src),
+        escape(field("alt"))
+    );
+    if field("link").is_empty() {
+        html.push_str(&image);
+    } else {
+        html.push_str(&format!(
+            "<a href=\"{}\">{}</a>",
+            escape(field("link")),
+            image
+        ));
+    }
+    if !field("caption").is_empty() {
+        html.push_str(&format!(
+            "<figcaption>{}</figcaption>",
+            escape(field("caption"))
+        ));
+    }
+    html.push_str("</figure>");
+    Ok(html)
+}
+
+fn render_list(shortcode: &Shortcode) -> Result<String, String> {
+    let source = match shortcode.arg("from", 0) {
+        Some(val) => val.to_string(),
+        None => return Err(String::from("list needs a directory or content_list entry")),
+    };
+    let limit = match shortcode.named.get("limit") {
+        Some(val) => match val.parse::<usize>() {
+            Ok(number) => number,
+            Err(_) => return Err(format!("limit isn't a number: {}", val)),
+        },
+        None => usize::MAX,
+    };
+    let metas: Vec<ContentMeta> = if does_directory_exist(source.clone()) {
+        read_full_dir_sorted(source)
+            .into_iter()
+            .filter(|x| !is_loading(&x.path))
+            .collect()
+    } else {
+        content_list_entries(&[source])
+            .into_iter()
+            .map(|x| x.1)
+            .collect()
+    };
+
+    let mut html = String::from("<ul class=\"shortcode-list\">\n");
+    for meta in metas.iter().take(limit) {
+        html.push_str(&format!(
+            "<li><a href=\"{}\">{}</a></li>\n",
+            escape(&meta.path),
+            escape(&meta.title)
+        ));
+    }
+    html.push_str("</ul>\n");
+    Ok(html)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_arguments() {
+        let shortcode =
+            parse_shortcode("figure \"/static/a b.png\" alt=\"A b\" class=wide").unwrap();
+        assert_eq!(shortcode.name, "figure");
+        assert_eq!(shortcode.arg("src", 0), Some("/static/a b.png"));
+        assert_eq!(shortcode.arg("alt", 1), Some("A b"));
+        assert_eq!(shortcode.arg("caption", 1), None);
+        assert!(parse_shortcode("  ").is_err());
+    }
+
+    #[test]
+    fn renders_around_markdown() {
+        register_shortcode(
+            "shout",
+            Box::new(|x: &Shortcode| Ok(format!("<b>{}</b>", x.arg("text", 0).unwrap_or("")))),
+        );
+        let source = "Say {{< shout hi >}} *now*\n\n{{< figure src=/a.png caption=\"A\" >}}\n\n`{{</* shout */>}}` {{< nope >}}";
+        let (markdown, rendered) = extract_shortcodes(source);
+        assert_eq!(rendered.len(), 3);
+        assert!(markdown.contains("N4SHORTCODE1X"));
+        assert!(markdown.contains("`{{< shout >}}`"));
+        assert_eq!(
+            restore_shortcodes(
+                "<p>Say N4SHORTCODE0X <em>now</em></p>\n<p>N4SHORTCODE1X</p>\n",
+                &rendered
+            ),
+            "<p>Say <b>hi</b> <em>now</em></p>\n<figure><img src=\"&#x2f;a.png\" alt=\"\"><figcaption>A</figcaption></figure>\n"
+        );
+        assert_eq!(
+            rendered[2],
+            "<!-- shortcode error: unknown shortcode nope -->"
+        );
+    }
+
+    #[test]
+    fn extracts_heading_sections() {
+        let html = "<h1>Install</h1>\n<p>Intro</p>\n<h2>Requirements</h2>\n<p>Rust</p>\n<h3>Optional</h3>\n<p>git</p>\n<hr>\n<h2>Build</h2>\n<p>cargo</p>\n";
+        assert_eq!(
+            extract_section(html, "requirements", true).unwrap(),
+            "<h2>Requirements</h2>\n<p>Rust</p>\n<h3>Optional</h3>\n<p>git</p>\n<hr>"
+        );
+        assert_eq!(
+            extract_section(html, "Build", false).unwrap(),
+            "<p>cargo</p>"
+        );
+        assert_eq!(extract_section(html, "Usage", true), None);
+
+        let shortcode =
+            parse_shortcode("transclude \"/docs/install#requirements\" heading=no").unwrap();
+        assert_eq!(
+            include_target(&shortcode).unwrap(),
+            (
+                String::from("/docs/install"),
+                Some(String::from("requirements"))
+            )
+        );
+        assert_eq!(
+            shortcode_problem("/docs/install", &shortcode).unwrap().1,
+            "includes itself"
+        );
+    }
+}
@@ -0,0 +1,285 @@
+//! Section sort orders
+//!
+//! MenuItemMeta.sort_by picks how the content listing and the submenu of a directory are ordered: "weight",
+//! "title", "created", "modified" or the name of another ContentMeta field holding a single value, submenus
+//! sort on the menu meta field of that name.  Fields that aren't in ContentMeta can't be sorted on, see
+//! sortable_fields().  MenuItemMeta.sort_order is "ascending" or "descending".  Entries without a value for the field go last either way.  Ties are broken by weight,
+//! then title (or directory name), then path so the order never depends on the file system.
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::path::Path;
+
+use chrono::prelude::*;
+use serde_json::Value;
+
+use crate::languages::split_language_prefix;
+use crate::page_dates::resolve_page_dates;
+use crate::{
+    read_file_creation_time, read_file_modified_time, webpath_to_localpath, ContentMeta, MenuItem,
+    MenuItemMeta,
+};
+
+pub const SORT_WEIGHT: &str = "weight";
+pub const SORT_TITLE: &str = "title";
+pub const SORT_CREATED: &str = "created";
+pub const SORT_MODIFIED: &str = "modified";
+pub const SORT_ASCENDING: &str = "ascending";
+pub const SORT_DESCENDING: &str = "descending";
+
+/// The value an entry is sorted on
+#[derive(Debug, Clone, PartialEq)]
+pub enum SortValue {
+    Missing,
+    Number(f64),
+    Date(DateTime<Utc>),
+    Text(String),
+}
+
+impl SortValue {
+    fn rank(&self) -> u8 {
+        match self {
+            SortValue::Number(_) => 0,
+            SortValue::Date(_) => 1,
+            SortValue::Text(_) => 2,
+            SortValue::Missing => 3,
+        }
+    }
+}
+
+/// Compare two sort values in ascending order, text is compared case insensitively
+pub fn compare_values(a: &SortValue, b: &SortValue) -> Ordering {
+    match (a, b) {
+        (SortValue::Number(x), SortValue::Number(y)) => x.partial_cmp(y).unwrap_or(Ordering::Equal),
+        (SortValue::Date(x), SortValue::Date(y)) => x.cmp(y),
+        (SortValue::Text(x), SortValue::Text(y)) => x.to_lowercase().cmp(&y.to_lowercase()),
+        _ => a.rank().cmp(&b.rank()), // Mixed types group together, missing values last
+    }
+}
+
+/// Sort value of a field of a serialized meta struct, lists sort on their first entry
+pub fn json_sort_value(value: Option<&Value>) -> SortValue {
+    match value {
+        Some(Value::Number(number)) => match number.as_f64() {
+            Some(val) => SortValue::Number(val),
+            None => SortValue::Missing,
+        },
+        Some(Value::String(text)) if !text.is_empty() => SortValue::Text(text.clone()),
+        Some(Value::Bool(flag)) => SortValue::Number(if *flag { 1.0 } else { 0.0 }),
+        Some(Value::Array(items)) => json_sort_value(items.first()),
+        _ => SortValue::Missing,
+    }
+}
+
+/// Stable sort of (value, entry) pairs
+///
+/// Parameters:
+///     entries(Vec<(SortValue, T)>), the entries with their sort values
+///     sort_order(&str), SORT_ASCENDING or SORT_DESCENDING, anything else is ascending
+///     tiebreak(Fn(&T, &T) -> Ordering), ascending order of entries with equal values
+/// Returns:
+///     Vec<T>, the sorted entries
+pub fn sort_entries<T, F: Fn(&T, &T) -> Ordering>(
+    mut entries: Vec<(SortValue, T)>,
+    sort_order: &str,
+    tiebreak: F,
+) -> Vec<T> {
+    let descending = sort_order == SORT_DESCENDING;
+    entries.sort_by(|a, b| {
+        let primary = match (&a.0, &b.0) {
+            (SortValue::Missing, SortValue::Missing) => Ordering::Equal,
+            (SortValue::Missing, _) => Ordering::Greater,
+            (_, SortValue::Missing) => Ordering::Less,
+            _ if descending => compare_values(&b.0, &a.0),
+            _ => compare_values(&a.0, &b.0),
+        };
+        primary.then_with(|| tiebreak(&a.1, &b.1))
+    });
+    entries.into_iter().map(|x| x.1).collect()
+}
+
+/// Weight, then title, then path, the order of content that sorts equal
+pub fn content_tiebreak(a: &ContentMeta, b: &ContentMeta) -> Ordering {
+    a.weight
+        .cmp(&b.weight)
+        .then_with(|| a.title.to_lowercase().cmp(&b.title.to_lowercase()))
+        .then_with(|| a.path.cmp(&b.path))
+}
+
+/// Every value sort_by can take: the SORT_ constants and the ContentMeta fields holding a single value.  The
+/// menu meta schema lists the same values so validate-meta reports anything else, and tolerant parsing puts
+/// the default back.
+pub fn sortable_fields() -> Vec<String> {
+    let mut fields: Vec<String> = [SORT_WEIGHT, SORT_TITLE, SORT_CREATED, SORT_MODIFIED]
+        .iter()
+        .map(|x| x.to_string())
+        .collect();
+    if let Ok(Value::Object(meta)) = serde_json::to_value(ContentMeta::default()) {
+        for (key, value) in meta {
+            if !value.is_array() && !value.is_object() && !fields.contains(&key) {
+                fields.push(key);
+            }
+        }
+    }
+    fields
+}
+
+/// Sort value of content for a sort_by setting
+///
+/// Parameters:
+///     meta(&ContentMeta), the content meta
+///     full_path_string(&str), absolute path of the content, only used for the created and modified dates
+///     sort_by(&str), one of sortable_fields()
+pub fn content_sort_value(meta: &ContentMeta, full_path_string: &str, sort_by: &str) -> SortValue {
+    match sort_by {
+        SORT_WEIGHT => SortValue::Number(meta.weight as f64),
+        SORT_TITLE => SortValue::Text(meta.title.clone()),
+        SORT_CREATED => SortValue::Date(resolve_page_dates(full_path_string, meta).created),
+        SORT_MODIFIED => SortValue::Date(resolve_page_dates(full_path_string, meta).modified),
+        field => json_sort_value(
+            serde_json::to_value(meta)
+                .ok()
+                .as_ref()
+                .and_then(|x| x.get(field)),
+        ),
+    }
+}
+
+/// Order content metas the way their section asks for
+///
+/// Parameters:
+///     entries(Vec<(String, ContentMeta)>), absolute path of each piece of content and its meta
+///     section_meta(&MenuItemMeta), the meta of the directory being listed
+/// Returns:
+///     Vec<ContentMeta>, the sorted metas
+pub fn sort_content_metas(
+    entries: Vec<(String, ContentMeta)>,
+    section_meta: &MenuItemMeta,
+) -> Vec<ContentMeta> {
+    let valued: Vec<(SortValue, ContentMeta)> = entries
+        .into_iter()
+        .map(|(full_path_string, meta)| {
+            (
+                content_sort_value(&meta, &full_path_string, &section_meta.sort_by),
+                meta,
+            )
+        })
+        .collect();
+    sort_entries(valued, &section_meta.sort_order, content_tiebreak)
+}
+
+/// Keys of a menu level in the order its parent directory asks for.  Titles are the directory names and the
+/// created and modified dates are those of the directories themselves.
+///
+/// Parameters:
+///     menus(&HashMap<String, MenuItem>), one level of tree_to_menus()
+///     section_meta(&MenuItemMeta), the meta of the directory holding that level
+/// Returns:
+///     Vec<String>, the keys of menus in display order
+pub fn sorted_menu_keys(
+    menus: &HashMap<String, MenuItem>,
+    section_meta: &MenuItemMeta,
+) -> Vec<String> {
+    let valued: Vec<(SortValue, (String, u32))> = menus
+        .iter()
+        .map(|(key, item)| {
+            let value = match section_meta.sort_by.as_str() {
+                SORT_WEIGHT => SortValue::Number(item.menu_meta.weight as f64),
+                SORT_TITLE => SortValue::Text(key.clone()),
+                SORT_CREATED | SORT_MODIFIED => {
+                    // Menus in other languages than the default have the language in front
+                    let (_, relative_path) = split_language_prefix(&item.relative_path);
+                    let local_path = webpath_to_localpath(relative_path);
+                    let dir_path = Path::new(&local_path);
+                    if !dir_path.exists() {
+                        SortValue::Missing
+                    } else if section_meta.sort_by == SORT_CREATED {
+                        SortValue::Date(read_file_creation_time(dir_path))
+                    } else {
+                        SortValue::Date(read_file_modified_time(dir_path))
+                    }
+                }
+                field => json_sort_value(
+                    serde_json::to_value(&item.menu_meta)
+                        .ok()
+                        .as_ref()
+                        .and_then(|x| x.get(field)),
+                ),
+            };
+            (value, (key.clone(), item.menu_meta.weight))
+        })
+        .collect();
+    sort_entries(valued, &section_meta.sort_order, |a, b| {
+        a.1.cmp(&b.1).then_with(|| a.0.cmp(&b.0))
+    })
+    .into_iter()
+    .map(|x| x.0)
+    .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn meta(title: &str, weight: u32, author: &str) -> ContentMeta {
+        ContentMeta {
+            title: title.to_string(),
+            path: format!("/{}", title),
+            weight,
+            author: author.to_string(),
+            ..ContentMeta::default()
+        }
+    }
+
+    fn titles(metas: Vec<ContentMeta>) -> Vec<String> {
+        metas.into_iter().map(|x| x.title).collect()
+    }
+
+    #[test]
+    fn equal_weights_fall_back_to_title() {
+        let entries = vec![
+            (String::new(), meta("c", 1, "")),
+            (String::new(), meta("b", 1, "")),
+            (String::new(), meta("a", 2, "")),
+        ];
+        let section = MenuItemMeta::default();
+        assert_eq!(
+            titles(sort_content_metas(entries, &section)),
+            vec!["b", "c", "a"]
+        );
+    }
+
+    #[test]
+    fn schema_lists_the_sortable_fields() {
+        let schema = crate::meta_schema::menu_meta_schema();
+        let mut listed: Vec<String> = schema["properties"]["sort_by"]["enum"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|x| x.as_str().unwrap().to_string())
+            .collect();
+        let mut fields = sortable_fields();
+        listed.sort();
+        fields.sort();
+        assert_eq!(listed, fields);
+        assert!(!fields.contains(&String::from("tags")));
+    }
+
+    #[test]
+    fn sorts_descending_on_custom_fields_with_missing_last() {
+        let entries = vec![
+            (String::new(), meta("a", 1, "")),
+            (String::new(), meta("b", 1, "Ann")),
+            (String::new(), meta("c", 1, "zed")),
+            (String::new(), meta("d", 0, "Ann")),
+        ];
+        let section = MenuItemMeta {
+            sort_by: String::from("author"),
+            sort_order: String::from(SORT_DESCENDING),
+            ..MenuItemMeta::default()
+        };
+        assert_eq!(
+            titles(sort_content_metas(entries, &section)),
+            vec!["c", "d", "b", "a"]
+        );
+    }
+}
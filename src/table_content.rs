@@ -0,0 +1,288 @@
+//! CSV and TSV table content
+//!
+//! .csv and .tsv content files are parsed with the csv crate and rendered as an HTML table.  How the table is
+//! shown comes from the `table` value of the page .content_meta, see TableOptions.
+//!
+//! When `header` is "auto" the first row is taken as the header if every cell in it is filled in, none of
+//! them is a number and no two are the same.  Without a header columns are named by number, "1" first.
+use serde_derive::{Deserialize, Serialize};
+use v_htmlescape::escape;
+
+use crate::sorting::{sort_entries, SortValue};
+
+pub const HEADER_AUTO: &str = "auto";
+pub const HEADER_YES: &str = "yes";
+pub const HEADER_NO: &str = "no";
+
+/// Table settings of a page
+///
+/// header
+///     "auto", "yes" or "no", is the first row the column names
+/// delimiter
+///     Single character field separator, empty for "," in .csv files and a tab in .tsv files
+/// columns
+///     Columns to show in order with their titles and alignment, empty shows every column as is
+/// sort_by, sort_order
+///     Column name to sort the rows on, "ascending" or "descending".  Numbers sort as numbers and empty cells
+///     go last.
+/// sortable
+///     Adds the "sortable" class to the table for a client side sorting script
+/// caption
+///     Table caption, left out when empty
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct TableOptions {
+    pub header: String,
+    pub delimiter: String,
+    pub columns: Vec<TableColumn>,
+    pub sort_by: String,
+    pub sort_order: String,
+    pub sortable: bool,
+    pub caption: String,
+}
+
+impl Default for TableOptions {
+    fn default() -> Self {
+        TableOptions {
+            header: String::from(HEADER_AUTO),
+            delimiter: String::from(""),
+            columns: Vec::new(),
+            sort_by: String::from(""),
+            sort_order: String::from("ascending"),
+            sortable: false,
+            caption: String::from(""),
+        }
+    }
+}
+
+/// A column to show
+///
+/// name
+///     Header of the column in the file, or its number counting from 1 when there is no header
+/// title
+///     Shown in place of the name when set
+/// align
+///     "left", "center" or "right", added as an "align-<value>" class on the cells
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+#[serde(default)]
+pub struct TableColumn {
+    pub name: String,
+    pub title: String,
+    pub align: String,
+}
+
+/// Parse delimited text into rows of fields
+///
+/// Parameters:
+///     text(&str), the raw file contents
+///     delimiter(u8), the field separator
+/// Returns:
+///     Result<Vec<Vec<String>>, String>, the rows with blank lines dropped, or the first parse error
+pub fn parse_table(text: &str, delimiter: u8) -> Result<Vec<Vec<String>>, String> {
+    let mut reader = csv::ReaderBuilder::new()
+        .has_headers(false)
+        .delimiter(delimiter)
+        .flexible(true)
+        .from_reader(text.as_bytes());
+    let mut rows: Vec<Vec<String>> = Vec::new();
+    for record in reader.records() {
+        match record {
+            Err(why) => return Err(format!("Table Parse Error: {}", why)),
+            Ok(record) => {
+                let row: Vec<String> = record.iter().map(|x| x.to_string()).collect();
+                if row.iter().any(|x| !x.trim().is_empty()) {
+                    rows.push(row);
+                }
+            }
+        }
+    }
+    Ok(rows)
+}
+
+/// Does the first row look like column names
+pub fn detect_header(rows: &[Vec<String>]) -> bool {
+    let first = match rows.first() {
+        Some(val) if rows.len() > 1 => val,
+        _ => return false, // A lone row is data
+    };
+    let mut seen: Vec<&str> = Vec::new();
+    for cell in first {
+        let cell = cell.trim();
+        if cell.is_empty() || cell.parse::<f64>().is_ok() || seen.contains(&cell) {
+            return false;
+        }
+        seen.push(cell);
+    }
+    true
+}
+
+/// The delimiter for a file extension and the page options, a set delimiter wins
+pub fn table_delimiter(extension: &str, options: &TableOptions) -> u8 {
+    match options.delimiter.as_bytes() {
+        [single] => *single,
+        _ if options.delimiter == "\\t" => b'\t',
+        _ if extension == "tsv" => b'\t',
+        _ => b',',
+    }
+}
+
+/// Parse and render a table file
+///
+/// Parameters:
+///     text(&str), the raw file contents
+///     delimiter(u8), the field separator, see table_delimiter()
+///     options(&TableOptions), from the page meta
+/// Returns:
+///     Result<String, String>, the table HTML or why the file couldn't be read
+pub fn render_table_content(
+    text: &str,
+    delimiter: u8,
+    options: &TableOptions,
+) -> Result<String, String> {
+    let mut rows = parse_table(text, delimiter)?;
+    let has_header = match options.header.as_str() {
+        HEADER_YES => !rows.is_empty(),
+        HEADER_NO => false,
+        _ => detect_header(&rows),
+    };
+    let names: Vec<String> = if has_header {
+        rows.remove(0)
+            .iter()
+            .map(|x| x.trim().to_string())
+            .collect()
+    } else {
+        let width = rows.iter().map(|x| x.len()).max().unwrap_or(0);
+        (1..=width).map(|x| x.to_string()).collect()
+    };
+
+    // Pick the columns to show as (index, title, align)
+    let mut columns: Vec<(usize, String, String)> = Vec::new();
+    if options.columns.is_empty() {
+        for (index, name) in names.iter().enumerate() {
+            columns.push((index, name.clone(), String::new()));
+        }
+    } else {
+        for column in &options.columns {
+            let index = match names.iter().position(|x| x == column.name.trim()) {
+                Some(val) => val,
+                None => return Err(format!("Table has no column named {}", column.name)),
+            };
+            let title = if column.title.is_empty() {
+                names[index].clone()
+            } else {
+                column.title.clone()
+            };
+            columns.push((index, title, column.align.clone()));
+        }
+    }
+
+    if !options.sort_by.is_empty() {
+        let index = match names.iter().position(|x| x == options.sort_by.trim()) {
+            Some(val) => val,
+            None => return Err(format!("Table has no column named {}", options.sort_by)),
+        };
+        let valued: Vec<(SortValue, Vec<String>)> = rows
+            .into_iter()
+            .map(|row| (cell_sort_value(row.get(index)), row))
+            .collect();
+        rows = sort_entries(valued, &options.sort_order, |_, _| {
+            std::cmp::Ordering::Equal // Keep file order for equal cells
+        });
+    }
+
+    let mut html = if options.sortable {
+        String::from("<table class=\"csv-table sortable\">\n")
+    } else {
+        String::from("<table class=\"csv-table\">\n")
+    };
+    if !options.caption.is_empty() {
+        html.push_str(&format!(
+            "<caption>{}</caption>\n",
+            escape(&options.caption)
+        ));
+    }
+    if has_header || !options.columns.is_empty() {
+        html.push_str("<thead>\n<tr>");
+        for (_, title, align) in &columns {
+            html.push_str(&format!("<th{}>{}</th>", align_class(align), escape(title)));
+        }
+        html.push_str("</tr>\n</thead>\n");
+    }
+    html.push_str("<tbody>\n");
+    for row in &rows {
+        html.push_str("<tr>");
+        for (index, _, align) in &columns {
+            let cell = row.get(*index).map(|x| x.as_str()).unwrap_or("");
+            html.push_str(&format!("<td{}>{}</td>", align_class(align), escape(cell)));
+        }
+        html.push_str("</tr>\n");
+    }
+    html.push_str("</tbody>\n</table>\n");
+    Ok(html)
+}
+
+fn cell_sort_value(cell: Option<&String>) -> SortValue {
+    let cell = match cell {
+        Some(val) if !val.trim().is_empty() => val.trim(),
+        _ => return SortValue::Missing,
+    };
+    match cell.parse::<f64>() {
+        Ok(number) => SortValue::Number(number),
+        Err(_) => SortValue::Text(cell.to_string()),
+    }
+}
+
+fn align_class(align: &str) -> String {
+    match align {
+        "left" | "center" | "right" => format!(" class=\"align-{}\"", align),
+        _ => String::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detects_headers() {
+        let rows = parse_table("name,size\na<b,2\nc,10\n", b',').unwrap();
+        assert!(detect_header(&rows));
+        let html = render_table_content("name,size\na<b,2\nc,10\n", b',', &TableOptions::default())
+            .unwrap();
+        assert!(html.contains("<th>name</th><th>size</th>"));
+        assert!(html.contains("<td>a&lt;b</td><td>2</td>"));
+
+        let numbers = parse_table("1\t2\n3\t4\n", b'\t').unwrap();
+        assert!(!detect_header(&numbers));
+        let html = render_table_content("1\t2\n3\t4\n", b'\t', &TableOptions::default()).unwrap();
+        assert!(!html.contains("<thead>"));
+        assert!(html.contains("<td>1</td><td>2</td>"));
+    }
+
+    #[test]
+    fn applies_column_and_sort_options() {
+        let options = TableOptions {
+            columns: vec![TableColumn {
+                name: String::from("size"),
+                title: String::from("Size (kB)"),
+                align: String::from("right"),
+            }],
+            sort_by: String::from("size"),
+            sort_order: String::from("descending"),
+            ..TableOptions::default()
+        };
+        let html = render_table_content("name,size\na,2\nb,\nc,10\n", b',', &options).unwrap();
+        assert!(html.contains("<th class=\"align-right\">Size (kB)</th>"));
+        let cells: Vec<&str> = html.matches("<td class=\"align-right\">").collect();
+        assert_eq!(cells.len(), 3);
+        assert!(html.find(">10<").unwrap() < html.find(">2<").unwrap());
+        assert!(html.find(">2<").unwrap() < html.find("></td>").unwrap());
+
+        let missing = TableOptions {
+            sort_by: String::from("colour"),
+            ..TableOptions::default()
+        };
+        assert!(render_table_content("name,size\na,2\n", b',', &missing).is_err());
+        assert_eq!(table_delimiter("tsv", &TableOptions::default()), b'\t');
+    }
+}
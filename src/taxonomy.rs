@@ -0,0 +1,308 @@
+//! Tags and categories
+//!
+//! ContentMeta.tags and ContentMeta.categories are collected across the content tree into a TaxonomyIndex.
+//! Every term gets a listing page at /tags/<slug> or /categories/<slug>, the index holds the pages for each
+//! term and a term cloud for navigation.  Like the backlink index it reads every meta file so build it once
+//! per build or server start.
+use std::collections::HashMap;
+
+use chrono::prelude::*;
+use serde_derive::{Deserialize, Serialize};
+
+use crate::page_dates::resolve_page_dates;
+use crate::publication::{content_is_visible, section_is_visible};
+use crate::{
+    content_local_path, content_path_with_extension, generate_content_state, load_config,
+    read_content_meta_file, tree_to_webpaths, ContentMeta, SiteMapEntry,
+};
+
+pub const TAXONOMY_TAGS: &str = "tags";
+pub const TAXONOMY_CATEGORIES: &str = "categories";
+
+/// A page filed under a term
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct TermPage {
+    pub path: String,
+    pub title: String,
+    pub description: String,
+    pub weight: u32,
+    pub created: DateTime<Utc>,
+    pub modified: DateTime<Utc>,
+}
+
+/// A single tag or category
+///
+/// name
+///     The term as written in the meta file of the first page using it, by web path
+/// slug
+///     Lowercase URL safe form used in the listing page path, terms with the same slug are merged
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Term {
+    pub name: String,
+    pub slug: String,
+    pub pages: Vec<TermPage>,
+}
+
+/// Term cloud entry, size runs from 1 for the least used terms to 5 for the most used
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct TermCloudEntry {
+    pub name: String,
+    pub slug: String,
+    pub path: String,
+    pub count: usize,
+    pub size: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TermOrder {
+    Weight, // Lightest first like directory listings
+    Date,   // Newest created first
+}
+
+/// Map of taxonomy name ("tags" or "categories") to its terms by slug
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct TaxonomyIndex {
+    pub taxonomies: HashMap<String, HashMap<String, Term>>,
+}
+
+impl TaxonomyIndex {
+    /// File a page under all of its tags and categories
+    pub fn insert(&mut self, meta: &ContentMeta, page: TermPage) {
+        for (taxonomy, terms) in &[
+            (TAXONOMY_TAGS, &meta.tags),
+            (TAXONOMY_CATEGORIES, &meta.categories),
+        ] {
+            for name in terms.iter() {
+                let slug = slugify(name);
+                if slug.is_empty() {
+                    continue;
+                }
+                let term = self
+                    .taxonomies
+                    .entry(taxonomy.to_string())
+                    .or_default()
+                    .entry(slug.clone())
+                    .or_insert_with(|| Term {
+                        name: name.trim().to_string(),
+                        slug,
+                        pages: Vec::new(),
+                    });
+                if !term.pages.iter().any(|x| x.path == page.path) {
+                    term.pages.push(page.clone());
+                }
+            }
+        }
+    }
+
+    pub fn term(&self, taxonomy: &str, slug: &str) -> Option<&Term> {
+        self.taxonomies.get(taxonomy).and_then(|x| x.get(slug))
+    }
+
+    /// The pages filed under a term, empty if the term doesn't exist
+    ///
+    /// Parameters:
+    ///     taxonomy(&str), TAXONOMY_TAGS or TAXONOMY_CATEGORIES
+    ///     slug(&str), the term slug from the listing page path
+    ///     order(TermOrder), by weight or by date
+    /// Returns:
+    ///     Vec<TermPage>, sorted with the web path as the tiebreaker
+    pub fn term_pages(&self, taxonomy: &str, slug: &str, order: TermOrder) -> Vec<TermPage> {
+        let mut pages = match self.term(taxonomy, slug) {
+            Some(term) => term.pages.clone(),
+            None => Vec::new(),
+        };
+        match order {
+            TermOrder::Weight => {
+                pages.sort_by(|a, b| a.weight.cmp(&b.weight).then_with(|| a.path.cmp(&b.path)))
+            }
+            TermOrder::Date => {
+                pages.sort_by(|a, b| b.created.cmp(&a.created).then_with(|| a.path.cmp(&b.path)))
+            }
+        }
+        pages
+    }
+
+    /// Every term of a taxonomy sorted by name with a relative size for rendering
+    pub fn term_cloud(&self, taxonomy: &str) -> Vec<TermCloudEntry> {
+        let terms = match self.taxonomies.get(taxonomy) {
+            Some(val) => val,
+            None => return Vec::new(),
+        };
+        let counts: Vec<usize> = terms.values().map(|x| x.pages.len()).collect();
+        let least = counts.iter().copied().min().unwrap_or(0);
+        let most = counts.iter().copied().max().unwrap_or(0);
+
+        let mut cloud: Vec<TermCloudEntry> = terms
+            .values()
+            .map(|term| {
+                let count = term.pages.len();
+                let size = if most == least {
+                    3
+                } else {
+                    1 + ((count - least) * 4 / (most - least)) as u32
+                };
+                TermCloudEntry {
+                    name: term.name.clone(),
+                    slug: term.slug.clone(),
+                    path: term_webpath(taxonomy, &term.slug),
+                    count,
+                    size,
+                }
+            })
+            .collect();
+        cloud.sort_by_key(|x| x.name.to_lowercase());
+        cloud
+    }
+
+    /// Web paths of every term listing page, sorted
+    pub fn term_webpaths(&self) -> Vec<String> {
+        let mut webpaths: Vec<String> = Vec::new();
+        for (taxonomy, terms) in &self.taxonomies {
+            for slug in terms.keys() {
+                webpaths.push(term_webpath(taxonomy, slug));
+            }
+        }
+        webpaths.sort();
+        webpaths
+    }
+}
+
+/// Build the taxonomy index for the whole content directory, unpublished content and sections are left out
+pub fn generate_taxonomy_index() -> TaxonomyIndex {
+    let mut index = TaxonomyIndex::default();
+
+    // Sorted so the name a term keeps doesn't depend on directory read order
+    let mut webpaths = tree_to_webpaths(&generate_content_state());
+    webpaths.sort();
+    for webpath in webpaths {
+        if !section_is_visible(&webpath) {
+            continue;
+        }
+        let full_path_string = content_local_path(&webpath);
+        let meta_path = content_path_with_extension(&full_path_string, "content_meta");
+        if !meta_path.exists() {
+            continue; // No meta file, no terms
+        }
+        let meta = read_content_meta_file(meta_path);
+        if (meta.tags.is_empty() && meta.categories.is_empty()) || !content_is_visible(&meta) {
+            continue;
+        }
+        let dates = resolve_page_dates(&full_path_string, &meta);
+        index.insert(
+            &meta,
+            TermPage {
+                path: webpath,
+                title: meta.title.clone(),
+                description: meta.description.clone(),
+                weight: meta.weight,
+                created: dates.created,
+                modified: dates.modified,
+            },
+        );
+    }
+    index
+}
+
+/// Sitemap entries for the term listing pages, lastmod is the newest change to a page under the term
+pub fn taxonomy_sitemap_entries(index: &TaxonomyIndex) -> Vec<SiteMapEntry> {
+    let config = load_config();
+    let mut entries: Vec<SiteMapEntry> = Vec::new();
+    for (taxonomy, terms) in &index.taxonomies {
+        for term in terms.values() {
+            let lastmod = match term.pages.iter().map(|x| x.modified).max() {
+                Some(val) => val,
+                None => continue,
+            };
+            entries.push(SiteMapEntry {
+                location: format!(
+                    "{}{}",
+                    config.prod_host.trim_end_matches("/"),
+                    term_webpath(taxonomy, &term.slug)
+                ),
+                lastmod,
+                priority: config.xml_priority.clone(),
+                alternates: Vec::new(),
+            });
+        }
+    }
+    entries.sort_by(|a, b| a.location.cmp(&b.location));
+    entries
+}
+
+/// Listing page web path of a term, such as "/tags/rust"
+pub fn term_webpath(taxonomy: &str, slug: &str) -> String {
+    format!("/{}/{}", taxonomy, slug)
+}
+
+/// Lowercase ASCII letters and digits with everything else collapsed to single dashes
+pub fn slugify(term: &str) -> String {
+    let mut slug = String::new();
+    for c in term.trim().chars() {
+        if c.is_ascii_alphanumeric() {
+            slug.push(c.to_ascii_lowercase());
+        } else if !slug.is_empty() && !slug.ends_with('-') {
+            slug.push('-');
+        }
+    }
+    slug.trim_end_matches('-').to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn page(path: &str, weight: u32, day: u32) -> TermPage {
+        let date = Utc.from_utc_datetime(
+            &NaiveDate::from_ymd_opt(2021, 3, day)
+                .unwrap()
+                .and_hms_opt(0, 0, 0)
+                .unwrap(),
+        );
+        TermPage {
+            path: path.to_string(),
+            title: path.to_string(),
+            description: String::from(""),
+            weight,
+            created: date,
+            modified: date,
+        }
+    }
+
+    #[test]
+    fn slugifies_terms() {
+        assert_eq!(slugify(" Rust Lang! "), "rust-lang");
+        assert_eq!(slugify("C++ / FFI"), "c-ffi");
+        assert_eq!(slugify("???"), "");
+    }
+
+    #[test]
+    fn indexes_and_sorts_term_pages() {
+        let mut index = TaxonomyIndex::default();
+        let tagged = |tags: &[&str]| ContentMeta {
+            tags: tags.iter().map(|x| x.to_string()).collect(),
+            ..ContentMeta::default()
+        };
+        index.insert(&tagged(&["Rust"]), page("/a", 10, 1));
+        index.insert(&tagged(&["rust", "web"]), page("/b", 5, 2));
+        index.insert(&tagged(&["rust"]), page("/c", 5, 3));
+
+        let by_weight: Vec<String> = index
+            .term_pages(TAXONOMY_TAGS, "rust", TermOrder::Weight)
+            .into_iter()
+            .map(|x| x.path)
+            .collect();
+        assert_eq!(by_weight, vec!["/b", "/c", "/a"]);
+        let by_date: Vec<String> = index
+            .term_pages(TAXONOMY_TAGS, "rust", TermOrder::Date)
+            .into_iter()
+            .map(|x| x.path)
+            .collect();
+        assert_eq!(by_date, vec!["/c", "/b", "/a"]);
+
+        let cloud = index.term_cloud(TAXONOMY_TAGS);
+        assert_eq!(cloud[0].name, "Rust");
+        assert_eq!((cloud[0].count, cloud[0].size), (3, 5));
+        assert_eq!((cloud[1].count, cloud[1].size), (1, 1));
+        assert_eq!(index.term_webpaths(), vec!["/tags/rust", "/tags/web"]);
+    }
+}